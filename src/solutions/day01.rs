@@ -1,88 +1,378 @@
 use anyhow::Context;
 use aoc_runner_derive::{aoc, aoc_generator};
-use fnv::FnvHashSet;
+use fnv::FnvHashMap;
+use std::ops::Range;
 
 type GeneratorOutput = Vec<u32>;
 type PartInput = [u32];
 
+const TARGET: u32 = 2020;
+
+/// Targets above this use hash maps instead of a flag array with one entry per possible value.
+const MAX_ARRAY_TARGET: u32 = 1 << 16;
+
 #[aoc_generator(day1)]
 pub fn generator(input: &[u8]) -> anyhow::Result<GeneratorOutput> {
     input
         .split(|b| *b == b'\n')
-        .map(|string| btoi::btou(string))
+        .map(btoi::btou)
         .collect::<Result<_, _>>()
         .context("parser error")
 }
 
-#[aoc(day1, part1, naive)]
-pub fn part_1(input: &PartInput) -> u32 {
-    for (idx, a) in input.iter().enumerate() {
-        for b in &input[idx + 1..] {
-            if a + b == 2020 {
-                return a * b;
+/// A set of entries summing to the requested target, ordered by their position in the input.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SumMatch {
+    pub indices: Vec<usize>,
+    pub values: Vec<u32>,
+}
+
+impl SumMatch {
+    fn from_indices(input: &[u32], mut indices: Vec<usize>) -> Self {
+        indices.sort_unstable();
+        let values = indices.iter().map(|&idx| input[idx]).collect();
+        SumMatch { indices, values }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Strategy {
+    /// Picks one of the strategies below based on `k`.
+    Auto,
+    Naive,
+    SinglePass,
+    SinglePassArray,
+    Sorted,
+    MeetInTheMiddle,
+}
+
+/// Finds `k` distinct entries of `input` that add up to `target`.
+pub fn k_sum(input: &[u32], k: usize, target: u32) -> Option<SumMatch> {
+    k_sum_with(input, k, target, Strategy::Auto)
+}
+
+/// Like [`k_sum`], but with a fixed strategy. The naive and single pass strategies only exist for
+/// pairs and triples and fall back to [`Strategy::Auto`] for any other `k`.
+pub fn k_sum_with(input: &[u32], k: usize, target: u32, strategy: Strategy) -> Option<SumMatch> {
+    match (strategy, k) {
+        (Strategy::Naive, 2) => pairs_naive(input, target).next(),
+        (Strategy::Naive, 3) => triples_naive(input, target).next(),
+        (Strategy::SinglePass, 2) | (Strategy::Auto, 2) => pairs_single_pass(input, target).next(),
+        (Strategy::SinglePass, 3) => triples_one_pass_less(input, target).next(),
+        (Strategy::SinglePassArray, 2) => two_sum_single_pass_array(input, target),
+        (Strategy::SinglePassArray, 3) => three_sum_one_pass_less(input, target),
+        (Strategy::Sorted, _) => k_sum_sorted(input, k, target),
+        (Strategy::MeetInTheMiddle, _) => k_sum_meet_in_the_middle(input, k, target),
+        (_, 0..=3) => k_sum_sorted(input, k, target),
+        _ => k_sum_meet_in_the_middle(input, k, target),
+    }
+}
+
+/// Multiplies the entries of the first match, failing if there is none or the product overflows.
+pub fn first_product(matches: impl IntoIterator<Item = SumMatch>) -> anyhow::Result<u32> {
+    matches
+        .into_iter()
+        .next()
+        .context("no entries sum up to the target")?
+        .values
+        .iter()
+        .try_fold(1u32, |acc, &x| acc.checked_mul(x))
+        .context("product overflowed")
+}
+
+pub fn pairs_naive(input: &[u32], target: u32) -> impl Iterator<Item = SumMatch> + '_ {
+    (0..input.len()).flat_map(move |idx_a| {
+        (idx_a + 1..input.len())
+            .filter(move |&idx_b| input[idx_a] as u64 + input[idx_b] as u64 == target as u64)
+            .map(move |idx_b| SumMatch::from_indices(input, vec![idx_a, idx_b]))
+    })
+}
+
+pub fn triples_naive(input: &[u32], target: u32) -> impl Iterator<Item = SumMatch> + '_ {
+    (0..input.len()).flat_map(move |idx_a| {
+        (idx_a + 1..input.len()).flat_map(move |idx_b| {
+            (idx_b + 1..input.len())
+                .filter(move |&idx_c| {
+                    input[idx_a] as u64 + input[idx_b] as u64 + input[idx_c] as u64 == target as u64
+                })
+                .map(move |idx_c| SumMatch::from_indices(input, vec![idx_a, idx_b, idx_c]))
+        })
+    })
+}
+
+pub fn pairs_single_pass(input: &[u32], target: u32) -> impl Iterator<Item = SumMatch> + '_ {
+    let mut seen = FnvHashMap::<u32, Vec<usize>>::default();
+    input.iter().enumerate().flat_map(move |(idx, &x)| {
+        let others = match target.checked_sub(x) {
+            Some(comp) => seen.get(&comp).cloned().unwrap_or_default(),
+            None => Vec::new(),
+        };
+        seen.entry(x).or_default().push(idx);
+        others
+            .into_iter()
+            .map(move |other| SumMatch::from_indices(input, vec![other, idx]))
+    })
+}
+
+pub fn triples_one_pass_less(input: &[u32], target: u32) -> impl Iterator<Item = SumMatch> + '_ {
+    let mut seen = FnvHashMap::<u32, Vec<usize>>::default();
+    input.iter().enumerate().flat_map(move |(idx_a, &a)| {
+        let mut matches = Vec::new();
+        for (idx_b, &b) in input.iter().enumerate().skip(idx_a + 1) {
+            if let Some(comp) = target.checked_sub(a).and_then(|t| t.checked_sub(b)) {
+                if let Some(others) = seen.get(&comp) {
+                    matches.extend(
+                        others
+                            .iter()
+                            .map(|&idx_c| SumMatch::from_indices(input, vec![idx_c, idx_a, idx_b])),
+                    );
+                }
             }
         }
+        seen.entry(a).or_default().push(idx_a);
+        matches
+    })
+}
+
+pub fn two_sum_single_pass_array(input: &[u32], target: u32) -> Option<SumMatch> {
+    if target > MAX_ARRAY_TARGET {
+        return pairs_single_pass(input, target).next();
+    }
+    let mut flags = vec![None; target as usize + 1];
+    for (idx, &x) in input.iter().enumerate() {
+        if x > target {
+            continue;
+        }
+        if let Some(other) = flags[(target - x) as usize] {
+            return Some(SumMatch::from_indices(input, vec![other, idx]));
+        }
+        flags[x as usize].get_or_insert(idx);
     }
-    unreachable!()
+    None
 }
 
-#[aoc(day1, part2, naive)]
-pub fn part_2(input: &PartInput) -> u32 {
-    for (idx_a, a) in input.iter().enumerate() {
-        for (idx_b, b) in input[idx_a + 1..].iter().enumerate() {
-            for c in &input[idx_b + 1..] {
-                if a + b + c == 2020 {
-                    return a * b * c;
+pub fn three_sum_one_pass_less(input: &[u32], target: u32) -> Option<SumMatch> {
+    if target > MAX_ARRAY_TARGET {
+        return triples_one_pass_less(input, target).next();
+    }
+    let mut flags = vec![None; target as usize + 1];
+    for (idx_a, &a) in input.iter().enumerate() {
+        for (idx_b, &b) in input.iter().enumerate().skip(idx_a + 1) {
+            if let Some(comp) = target.checked_sub(a).and_then(|t| t.checked_sub(b)) {
+                if let Some(idx_c) = flags[comp as usize] {
+                    return Some(SumMatch::from_indices(input, vec![idx_c, idx_a, idx_b]));
                 }
             }
         }
+        if a <= target {
+            flags[a as usize].get_or_insert(idx_a);
+        }
     }
-    unreachable!()
+    None
 }
 
-#[aoc(day1, part1, single_pass)]
-pub fn part_1_single(input: &PartInput) -> u32 {
-    let mut set = FnvHashSet::with_capacity_and_hasher(input.len(), Default::default());
-    for x in input {
-        let comp = 2020 - x;
-        if set.contains(&comp) {
-            return x * comp;
-        }
-        set.insert(*x);
+/// Sorts the entries once and then fixes the smallest remaining entry until only a pair is
+/// left, which is found with two pointers. Runs in O(n^(k-1)) for k >= 2, so it is only the best
+/// choice up to triples.
+pub fn k_sum_sorted(input: &[u32], k: usize, target: u32) -> Option<SumMatch> {
+    if k > input.len() {
+        return None;
+    }
+    let mut sorted = input
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(idx, x)| (x as u64, idx))
+        .collect::<Vec<_>>();
+    sorted.sort_unstable();
+
+    let mut chosen = Vec::with_capacity(k);
+    if k_sum_sorted_inner(&sorted, k, target as u64, &mut chosen) {
+        Some(SumMatch::from_indices(input, chosen))
+    } else {
+        None
     }
-    unreachable!()
 }
 
-#[aoc(day1, part1, single_pass_array)]
-pub fn part_1_single_array(input: &PartInput) -> u32 {
-    let mut flags = [false; 2020];
-    flags[input[0] as usize] = true;
-    for x in &input[1..] {
-        let comp = 2020 - x;
-        if flags[comp as usize] {
-            return x * comp;
+fn k_sum_sorted_inner(
+    sorted: &[(u64, usize)],
+    k: usize,
+    target: u64,
+    chosen: &mut Vec<usize>,
+) -> bool {
+    match k {
+        0 => target == 0,
+        1 => match sorted.binary_search_by_key(&target, |&(x, _)| x) {
+            Ok(pos) => {
+                chosen.push(sorted[pos].1);
+                true
+            }
+            Err(_) => false,
+        },
+        2 => {
+            if sorted.len() < 2 {
+                return false;
+            }
+            let (mut lo, mut hi) = (0, sorted.len() - 1);
+            while lo < hi {
+                let sum = sorted[lo].0 + sorted[hi].0;
+                if sum == target {
+                    chosen.extend_from_slice(&[sorted[lo].1, sorted[hi].1]);
+                    return true;
+                } else if sum < target {
+                    lo += 1;
+                } else {
+                    hi -= 1;
+                }
+            }
+            false
+        }
+        _ => {
+            if sorted.len() < k {
+                return false;
+            }
+            let max_rest = sorted[sorted.len() - (k - 1)..]
+                .iter()
+                .map(|&(x, _)| x)
+                .sum::<u64>();
+            for (pos, &(x, idx)) in sorted[..=sorted.len() - k].iter().enumerate() {
+                if x * k as u64 > target {
+                    break;
+                }
+                if (pos > 0 && sorted[pos - 1].0 == x) || x + max_rest < target {
+                    continue;
+                }
+                chosen.push(idx);
+                if k_sum_sorted_inner(&sorted[pos + 1..], k - 1, target - x, chosen) {
+                    return true;
+                }
+                chosen.pop();
+            }
+            false
         }
-        flags[*x as usize] = true;
     }
-    unreachable!()
 }
 
-#[aoc(day1, part2, one_pass_less)]
-pub fn part_2_one_pass_less(input: &PartInput) -> u32 {
-    let mut flags = [false; 2020];
-    flags[input[0] as usize] = true;
-    for (idx, a) in input[1..].iter().enumerate() {
-        for b in &input[idx + 1..] {
-            if a + b <= 2020 {
-                let comp = 2020 - (a + b);
-                if flags[comp as usize] {
-                    return a * b * comp;
+/// Splits every match into its `k / 2` entries with the smallest indices and the rest. Going
+/// through the input once, the sums of all lower halves ending before the current entry are kept
+/// in a map, which the upper halves starting at the current entry are looked up in. Runs in
+/// O(n^ceil(k/2)) time for k >= 2, which makes 4-sums quadratic.
+pub fn k_sum_meet_in_the_middle(input: &[u32], k: usize, target: u32) -> Option<SumMatch> {
+    if k < 2 || k > input.len() {
+        return k_sum_sorted(input, k, target);
+    }
+    let (lower_size, upper_size) = (k / 2, k - k / 2);
+    let target = target as u64;
+    let mut lower_sums = FnvHashMap::<u64, Vec<usize>>::default();
+    let mut chosen = Vec::with_capacity(upper_size);
+
+    for split in 0..input.len() {
+        let mut found = None;
+        chosen.push(split);
+        for_each_subset(
+            input,
+            split + 1..input.len(),
+            upper_size - 1,
+            input[split] as u64,
+            &mut chosen,
+            &mut |upper, sum| {
+                let lower = target
+                    .checked_sub(sum)
+                    .and_then(|rest| lower_sums.get(&rest));
+                if let Some(lower) = lower {
+                    found = Some(lower.iter().chain(upper).copied().collect());
                 }
-            }
+                found.is_some()
+            },
+        );
+        if let Some(indices) = found {
+            return Some(SumMatch::from_indices(input, indices));
         }
-        flags[*a as usize] = true;
+        for_each_subset(
+            input,
+            0..split,
+            lower_size - 1,
+            input[split] as u64,
+            &mut chosen,
+            &mut |lower, sum| {
+                if sum <= target {
+                    lower_sums.entry(sum).or_insert_with(|| lower.to_vec());
+                }
+                false
+            },
+        );
+        chosen.pop();
     }
-    unreachable!()
+    None
+}
+
+/// Calls `f` with `chosen` extended by every `size`-subset of `range` and the sum of its entries,
+/// until `f` returns `true`.
+fn for_each_subset(
+    input: &[u32],
+    range: Range<usize>,
+    size: usize,
+    sum: u64,
+    chosen: &mut Vec<usize>,
+    f: &mut impl FnMut(&[usize], u64) -> bool,
+) -> bool {
+    if size == 0 {
+        return f(chosen, sum);
+    }
+    if range.end < range.start + size {
+        return false;
+    }
+    for idx in range.start..=range.end - size {
+        chosen.push(idx);
+        let done = for_each_subset(
+            input,
+            idx + 1..range.end,
+            size - 1,
+            sum + input[idx] as u64,
+            chosen,
+            f,
+        );
+        chosen.pop();
+        if done {
+            return true;
+        }
+    }
+    false
+}
+
+#[aoc(day1, part1, naive)]
+pub fn part_1(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 2, TARGET, Strategy::Naive))
+}
+
+#[aoc(day1, part2, naive)]
+pub fn part_2(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 3, TARGET, Strategy::Naive))
+}
+
+#[aoc(day1, part1, single_pass)]
+pub fn part_1_single(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 2, TARGET, Strategy::SinglePass))
+}
+
+#[aoc(day1, part1, single_pass_array)]
+pub fn part_1_single_array(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 2, TARGET, Strategy::SinglePassArray))
+}
+
+#[aoc(day1, part2, one_pass_less)]
+pub fn part_2_one_pass_less(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 3, TARGET, Strategy::SinglePass))
+}
+
+#[aoc(day1, part2, one_pass_less_array)]
+pub fn part_2_one_pass_less_array(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 3, TARGET, Strategy::SinglePassArray))
+}
+
+#[aoc(day1, part2, sorted)]
+pub fn part_2_sorted(input: &PartInput) -> anyhow::Result<u32> {
+    first_product(k_sum_with(input, 3, TARGET, Strategy::Sorted))
 }
 
 #[cfg(test)]
@@ -90,8 +380,9 @@ mod tests {
     use super::*;
     const LIST: [u32; 6] = [1721, 979, 366, 299, 675, 1456];
 
-    fn part_1_tests(f: fn(input: &PartInput) -> u32) {
-        assert_eq!(f(&LIST), 514579);
+    fn part_1_tests(f: fn(input: &PartInput) -> anyhow::Result<u32>) {
+        assert_eq!(f(&LIST).unwrap(), 514579);
+        assert!(f(&[1, 2, 3]).is_err());
     }
 
     #[test]
@@ -109,8 +400,9 @@ mod tests {
         part_1_tests(super::part_1_single_array);
     }
 
-    fn part_2_tests(f: fn(input: &PartInput) -> u32) {
-        assert_eq!(f(&LIST), 241861950);
+    fn part_2_tests(f: fn(input: &PartInput) -> anyhow::Result<u32>) {
+        assert_eq!(f(&LIST).unwrap(), 241861950);
+        assert!(f(&[1, 2, 3]).is_err());
     }
 
     #[test]
@@ -122,4 +414,111 @@ mod tests {
     fn part_2_one_pass_less() {
         part_2_tests(super::part_2_one_pass_less);
     }
+
+    #[test]
+    fn part_2_one_pass_less_array() {
+        part_2_tests(super::part_2_one_pass_less_array);
+    }
+
+    #[test]
+    fn part_2_sorted() {
+        part_2_tests(super::part_2_sorted);
+    }
+
+    #[test]
+    fn all_matches_test() {
+        const LEDGER: [u32; 6] = [10, 20, 30, 40, 50, 60];
+        let sorted_indices = |matches: Vec<SumMatch>| {
+            let mut indices = matches.into_iter().map(|m| m.indices).collect::<Vec<_>>();
+            indices.sort();
+            indices
+        };
+        let pairs = vec![vec![0, 5], vec![1, 4], vec![2, 3]];
+        assert_eq!(sorted_indices(pairs_naive(&LEDGER, 70).collect()), pairs);
+        assert_eq!(
+            sorted_indices(pairs_single_pass(&LEDGER, 70).collect()),
+            pairs
+        );
+        let triples = vec![vec![0, 1, 5], vec![0, 2, 4], vec![1, 2, 3]];
+        assert_eq!(
+            sorted_indices(triples_naive(&LEDGER, 90).collect()),
+            triples
+        );
+        assert_eq!(
+            sorted_indices(triples_one_pass_less(&LEDGER, 90).collect()),
+            triples
+        );
+        assert_eq!(pairs_single_pass(&LEDGER, 1000).next(), None);
+        assert!(first_product(pairs_naive(&[u32::MAX / 2, u32::MAX / 2 + 1], u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn k_sum_test() {
+        assert_eq!(
+            k_sum(&LIST, 2, 2020),
+            Some(SumMatch {
+                indices: vec![0, 3],
+                values: vec![1721, 299],
+            })
+        );
+        assert_eq!(
+            k_sum(&LIST, 3, 2020),
+            Some(SumMatch {
+                indices: vec![1, 2, 4],
+                values: vec![979, 366, 675],
+            })
+        );
+        let four = k_sum(&LIST, 4, 366 + 299 + 675 + 1456).unwrap();
+        assert_eq!(four.values.iter().sum::<u32>(), 366 + 299 + 675 + 1456);
+        assert_eq!(four.indices.len(), 4);
+        assert_eq!(k_sum(&LIST, 1, 675).unwrap().indices, vec![4]);
+        assert_eq!(k_sum(&LIST, 2, 1721 * 2), None);
+        assert_eq!(k_sum(&LIST, 7, 0), None);
+        assert_eq!(k_sum(&[1010, 1010], 2, 2020).unwrap().indices, vec![0, 1]);
+    }
+
+    #[test]
+    fn meet_in_the_middle_test() {
+        const LEDGER: [u32; 10] = [31, 7, 12, 45, 3, 28, 19, 50, 8, 23];
+        for k in 1..=7 {
+            for target in 0..200 {
+                let sorted = k_sum_sorted(&LEDGER, k, target);
+                let middle = k_sum_meet_in_the_middle(&LEDGER, k, target);
+                assert_eq!(
+                    middle.is_some(),
+                    sorted.is_some(),
+                    "k = {}, target = {}",
+                    k,
+                    target
+                );
+                if let Some(middle) = middle {
+                    assert_eq!(middle.indices.len(), k);
+                    assert_eq!(middle.values.iter().sum::<u32>(), target);
+                }
+            }
+        }
+        assert_eq!(
+            k_sum_with(&LIST, 4, 366 + 299 + 675 + 1456, Strategy::MeetInTheMiddle)
+                .unwrap()
+                .indices,
+            vec![2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn large_target_test() {
+        let ledger = [u32::MAX - 10, 4, 6, 1];
+        assert_eq!(
+            two_sum_single_pass_array(&ledger, u32::MAX - 4)
+                .unwrap()
+                .indices,
+            vec![0, 2]
+        );
+        assert_eq!(
+            three_sum_one_pass_less(&ledger, u32::MAX - 5)
+                .unwrap()
+                .indices,
+            vec![0, 1, 3]
+        );
+    }
 }
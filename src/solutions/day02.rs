@@ -1,5 +1,6 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 use nom::IResult;
+use std::{fmt, ops::RangeInclusive};
 
 type GeneratorOutput = Vec<PasswordEntry>;
 type PartInput = [PasswordEntry];
@@ -45,29 +46,498 @@ fn parse_password(input: &[u8]) -> IResult<&[u8], PasswordEntry> {
     ))
 }
 
-#[aoc(day2, part1)]
-pub fn part_1(input: &PartInput) -> usize {
-    input.iter().filter(|&pw| pw.is_valid()).count()
+impl PasswordEntry {
+    pub fn min(&self) -> u8 {
+        self.min
+    }
+
+    pub fn max(&self) -> u8 {
+        self.max
+    }
+
+    pub fn letter(&self) -> u8 {
+        self.letter
+    }
+
+    pub fn password(&self) -> &[u8] {
+        &self.password
+    }
 }
 
-impl PasswordEntry {
-    fn is_valid(&self) -> bool {
-        let count = bytecount::count(&self.password, self.letter) as u8;
-        count >= self.min && count <= self.max
+pub trait PasswordPolicy {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation>;
+
+    fn allows(&self, entry: &PasswordEntry) -> bool {
+        self.check(entry).is_ok()
+    }
+}
+
+impl<P: PasswordPolicy + ?Sized> PasswordPolicy for Box<P> {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        (**self).check(entry)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Violation {
+    CountOutOfRange {
+        count: usize,
+        min: u8,
+        max: u8,
+    },
+    PositionOutOfRange {
+        position: u8,
+        length: usize,
+    },
+    MatchedPositions(Vec<u8>),
+    OutsideClass {
+        position: usize,
+        character: u8,
+    },
+    MissingClass,
+    TooFewDistinctLetters {
+        distinct: usize,
+        min: usize,
+    },
+    ForbiddenSubstring {
+        substring: Vec<u8>,
+        position: usize,
+    },
+    LettersOutOfRange {
+        count: usize,
+        min: usize,
+        max: usize,
+    },
+    NoAlternative(Vec<Violation>),
+    Negated,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::CountOutOfRange { count, min, max } => {
+                write!(
+                    f,
+                    "letter occurs {} times, expected {}..={}",
+                    count, min, max
+                )
+            }
+            Violation::PositionOutOfRange { position, length } => write!(
+                f,
+                "position {} is outside of the password of length {}",
+                position, length
+            ),
+            Violation::MatchedPositions(positions) => write!(
+                f,
+                "letter matches at positions {:?}, expected exactly one",
+                positions
+            ),
+            Violation::OutsideClass {
+                position,
+                character,
+            } => write!(
+                f,
+                "character {:?} at position {} is outside of the class",
+                *character as char,
+                position + 1
+            ),
+            Violation::MissingClass => write!(f, "no character of the class occurs"),
+            Violation::TooFewDistinctLetters { distinct, min } => write!(
+                f,
+                "{} distinct letters, expected at least {}",
+                distinct, min
+            ),
+            Violation::ForbiddenSubstring {
+                substring,
+                position,
+            } => write!(
+                f,
+                "forbidden substring {:?} at position {}",
+                String::from_utf8_lossy(substring),
+                position + 1
+            ),
+            Violation::LettersOutOfRange { count, min, max } => write!(
+                f,
+                "letters occur {} times in total, expected {}..={}",
+                count, min, max
+            ),
+            Violation::NoAlternative(violations) => {
+                write!(f, "no alternative is satisfied (")?;
+                for (idx, violation) in violations.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", violation)?;
+                }
+                write!(f, ")")
+            }
+            Violation::Negated => write!(f, "negated policy is satisfied"),
+        }
+    }
+}
+
+/// The letter occurs between `min` and `max` times.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CountInRange;
+
+impl PasswordPolicy for CountInRange {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        let count = bytecount::count(&entry.password, entry.letter);
+        if (entry.min as usize..=entry.max as usize).contains(&count) {
+            Ok(())
+        } else {
+            Err(Violation::CountOutOfRange {
+                count,
+                min: entry.min,
+                max: entry.max,
+            })
+        }
+    }
+}
+
+/// The letter occurs at exactly one of the 1-based positions `min` and `max`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExactlyOnePosition;
+
+impl PasswordPolicy for ExactlyOnePosition {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        let mut matched = Vec::with_capacity(2);
+        for &position in &[entry.min, entry.max] {
+            match (position as usize)
+                .checked_sub(1)
+                .and_then(|idx| entry.password.get(idx))
+            {
+                Some(&c) if c == entry.letter => matched.push(position),
+                Some(_) => {}
+                None => {
+                    return Err(Violation::PositionOutOfRange {
+                        position,
+                        length: entry.password.len(),
+                    })
+                }
+            }
+        }
+        if matched.len() == 1 {
+            Ok(())
+        } else {
+            Err(Violation::MatchedPositions(matched))
+        }
     }
+}
 
-    fn is_valid_2(&self) -> bool {
-        [self.min, self.max]
+/// A set of byte ranges like `[a-z0-9]`, optionally negated with `[^...]`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CharacterClass {
+    ranges: Vec<RangeInclusive<u8>>,
+    negated: bool,
+}
+
+impl CharacterClass {
+    pub fn new(ranges: Vec<RangeInclusive<u8>>, negated: bool) -> Self {
+        CharacterClass { ranges, negated }
+    }
+
+    pub fn contains(&self, c: u8) -> bool {
+        self.ranges.iter().any(|range| range.contains(&c)) != self.negated
+    }
+}
+
+/// Every character of the password is part of the class.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OnlyClass(pub CharacterClass);
+
+impl PasswordPolicy for OnlyClass {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        match entry.password.iter().position(|&c| !self.0.contains(c)) {
+            Some(position) => Err(Violation::OutsideClass {
+                position,
+                character: entry.password[position],
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// At least one character of the password is part of the class.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RequiresClass(pub CharacterClass);
+
+impl PasswordPolicy for RequiresClass {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        if entry.password.iter().any(|&c| self.0.contains(c)) {
+            Ok(())
+        } else {
+            Err(Violation::MissingClass)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MinDistinctLetters(pub usize);
+
+impl PasswordPolicy for MinDistinctLetters {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        let mut seen = [false; 256];
+        entry.password.iter().for_each(|&c| seen[c as usize] = true);
+        let distinct = seen.iter().filter(|&&s| s).count();
+        if distinct >= self.0 {
+            Ok(())
+        } else {
+            Err(Violation::TooFewDistinctLetters {
+                distinct,
+                min: self.0,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ForbiddenSubstrings(pub Vec<Vec<u8>>);
+
+impl PasswordPolicy for ForbiddenSubstrings {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        for forbidden in self.0.iter().filter(|forbidden| !forbidden.is_empty()) {
+            if let Some(position) = entry
+                .password
+                .windows(forbidden.len())
+                .position(|window| window == &forbidden[..])
+            {
+                return Err(Violation::ForbiddenSubstring {
+                    substring: forbidden.clone(),
+                    position,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The letters in the set occur between `min` and `max` times in total.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LettersInRange {
+    pub letters: Vec<u8>,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl PasswordPolicy for LettersInRange {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        let count = entry
+            .password
             .iter()
-            .filter(|&idx| self.password[(idx - 1) as usize] == self.letter)
-            .count()
-            == 1
+            .filter(|c| self.letters.contains(c))
+            .count();
+        if (self.min..=self.max).contains(&count) {
+            Ok(())
+        } else {
+            Err(Violation::LettersOutOfRange {
+                count,
+                min: self.min,
+                max: self.max,
+            })
+        }
     }
 }
 
+pub struct And(pub Vec<Box<dyn PasswordPolicy>>);
+
+impl PasswordPolicy for And {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        self.0.iter().try_for_each(|policy| policy.check(entry))
+    }
+}
+
+pub struct Or(pub Vec<Box<dyn PasswordPolicy>>);
+
+impl PasswordPolicy for Or {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        let mut violations = Vec::with_capacity(self.0.len());
+        for policy in &self.0 {
+            match policy.check(entry) {
+                Ok(()) => return Ok(()),
+                Err(violation) => violations.push(violation),
+            }
+        }
+        Err(Violation::NoAlternative(violations))
+    }
+}
+
+pub struct Not(pub Box<dyn PasswordPolicy>);
+
+impl PasswordPolicy for Not {
+    fn check(&self, entry: &PasswordEntry) -> Result<(), Violation> {
+        match self.0.check(entry) {
+            Ok(()) => Err(Violation::Negated),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Parses a policy description such as `and(count, not(forbid:abc|xyz), distinct:4)`.
+///
+/// Available policies are `count`, `positions`, `only:[class]`, `requires:[class]`,
+/// `distinct:N`, `forbid:word|word`, `letters:abc:MIN-MAX` and the combinators `and(...)`,
+/// `or(...)` and `not(...)`.
+pub fn parse_policy(input: &str) -> anyhow::Result<Box<dyn PasswordPolicy>> {
+    use nom::combinator::all_consuming;
+    Ok(all_consuming(policy)(input.trim().as_bytes())
+        .map_err(|e| e.map(|e| (String::from_utf8_lossy(e.input).into_owned(), e.code)))?
+        .1)
+}
+
+fn policy(input: &[u8]) -> IResult<&[u8], Box<dyn PasswordPolicy>> {
+    use crate::util::parsers::unsigned_number;
+    use nom::{
+        branch::alt,
+        bytes::complete::{tag, take_while1},
+        character::complete::space0,
+        combinator::map,
+        multi::separated_list1,
+        sequence::{delimited, preceded, separated_pair, tuple},
+    };
+
+    let list = |input| separated_list1(tuple((space0, tag(","), space0)), policy)(input);
+    let word = |input| take_while1(|c: u8| c.is_ascii_alphanumeric())(input);
+
+    alt((
+        map(delimited(tag("and("), list, tag(")")), |p| {
+            Box::new(And(p)) as Box<dyn PasswordPolicy>
+        }),
+        map(delimited(tag("or("), list, tag(")")), |p| {
+            Box::new(Or(p)) as Box<dyn PasswordPolicy>
+        }),
+        map(delimited(tag("not("), policy, tag(")")), |p| {
+            Box::new(Not(p)) as Box<dyn PasswordPolicy>
+        }),
+        map(tag("count"), |_| Box::new(CountInRange) as _),
+        map(tag("positions"), |_| Box::new(ExactlyOnePosition) as _),
+        map(preceded(tag("only:"), character_class), |c| {
+            Box::new(OnlyClass(c)) as _
+        }),
+        map(preceded(tag("requires:"), character_class), |c| {
+            Box::new(RequiresClass(c)) as _
+        }),
+        map(preceded(tag("distinct:"), unsigned_number::<usize>), |n| {
+            Box::new(MinDistinctLetters(n)) as _
+        }),
+        map(
+            preceded(tag("forbid:"), separated_list1(tag("|"), word)),
+            |words| {
+                Box::new(ForbiddenSubstrings(
+                    words.into_iter().map(<[u8]>::to_vec).collect(),
+                )) as _
+            },
+        ),
+        map(
+            tuple((
+                tag("letters:"),
+                word,
+                tag(":"),
+                separated_pair(unsigned_number::<usize>, tag("-"), unsigned_number::<usize>),
+            )),
+            |(_, letters, _, (min, max))| {
+                Box::new(LettersInRange {
+                    letters: letters.to_vec(),
+                    min,
+                    max,
+                }) as _
+            },
+        ),
+    ))(input)
+}
+
+fn character_class(input: &[u8]) -> IResult<&[u8], CharacterClass> {
+    use nom::{
+        bytes::complete::{tag, take},
+        combinator::{map, opt, verify},
+        multi::many1,
+        sequence::{delimited, preceded, tuple},
+    };
+
+    let class_char = |input| {
+        map(
+            verify(take(1usize), |c: &[u8]| c[0] != b']'),
+            |c: &[u8]| c[0],
+        )(input)
+    };
+    map(
+        delimited(
+            tag("["),
+            tuple((
+                opt(tag("^")),
+                many1(map(
+                    tuple((class_char, opt(preceded(tag("-"), class_char)))),
+                    |(start, end)| start..=end.unwrap_or(start),
+                )),
+            )),
+            tag("]"),
+        ),
+        |(negated, ranges)| CharacterClass::new(ranges, negated.is_some()),
+    )(input)
+}
+
+pub fn count_valid(input: &[PasswordEntry], policy: &dyn PasswordPolicy) -> usize {
+    input.iter().filter(|&pw| policy.allows(pw)).count()
+}
+
+/// Counts the entries allowed by each policy in a single pass over the entries.
+pub fn count_valid_per_policy(
+    input: &[PasswordEntry],
+    policies: &[&dyn PasswordPolicy],
+) -> Vec<usize> {
+    let mut counts = vec![0; policies.len()];
+    for entry in input {
+        for (count, policy) in counts.iter_mut().zip(policies) {
+            *count += policy.allows(entry) as usize;
+        }
+    }
+    counts
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Failure<'a> {
+    pub line: usize,
+    pub entry: &'a PasswordEntry,
+    pub violation: Violation,
+}
+
+impl fmt::Display for Failure<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}-{} {}: {}: {}",
+            self.line,
+            self.entry.min,
+            self.entry.max,
+            self.entry.letter as char,
+            String::from_utf8_lossy(&self.entry.password),
+            self.violation
+        )
+    }
+}
+
+/// Explains every entry rejected by the policy. Lines are 1-based, one entry per line.
+pub fn failures<'a>(
+    input: &'a [PasswordEntry],
+    policy: &'a dyn PasswordPolicy,
+) -> impl Iterator<Item = Failure<'a>> + 'a {
+    input.iter().enumerate().filter_map(move |(idx, entry)| {
+        policy.check(entry).err().map(|violation| Failure {
+            line: idx + 1,
+            entry,
+            violation,
+        })
+    })
+}
+
+#[aoc(day2, part1)]
+pub fn part_1(input: &PartInput) -> usize {
+    count_valid(input, &CountInRange)
+}
+
 #[aoc(day2, part2)]
 pub fn part_2(input: &PartInput) -> usize {
-    input.iter().filter(|&pw| pw.is_valid_2()).count()
+    count_valid(input, &ExactlyOnePosition)
 }
 
 #[cfg(test)]
@@ -84,4 +554,68 @@ mod tests {
     fn part_2_test() {
         assert_eq!(part_2(&generator(RULES).unwrap()), 1);
     }
+
+    #[test]
+    fn policy_test() {
+        let entries = generator(RULES).unwrap();
+        let policies = [
+            "count",
+            "positions",
+            "and(count, positions)",
+            "or(count,positions)",
+            "not(count)",
+            "only:[a-e]",
+            "requires:[^a-c]",
+            "distinct:2",
+            "forbid:cc|xyz",
+            "letters:ace:2-3",
+        ]
+        .iter()
+        .map(|spec| parse_policy(spec).unwrap())
+        .collect::<Vec<_>>();
+        let policies = policies.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+        assert_eq!(
+            count_valid_per_policy(&entries, &policies),
+            vec![2, 1, 1, 2, 1, 2, 2, 2, 2, 2]
+        );
+        assert!(parse_policy("and(count").is_err());
+        assert!(parse_policy("unknown").is_err());
+    }
+
+    #[test]
+    fn failures_test() {
+        let entries = generator(b"1-3 a: abcde\n1-3 b: cdefg\n0-9 c: ccccccccc").unwrap();
+        let count_failures = failures(&entries, &CountInRange).collect::<Vec<_>>();
+        assert_eq!(count_failures.len(), 1);
+        assert_eq!(count_failures[0].line, 2);
+        assert_eq!(
+            count_failures[0].violation,
+            Violation::CountOutOfRange {
+                count: 0,
+                min: 1,
+                max: 3
+            }
+        );
+        assert_eq!(
+            count_failures[0].to_string(),
+            "line 2: 1-3 b: cdefg: letter occurs 0 times, expected 1..=3"
+        );
+
+        let position_failures = failures(&entries, &ExactlyOnePosition)
+            .map(|failure| (failure.line, failure.violation))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            position_failures,
+            vec![
+                (2, Violation::MatchedPositions(vec![])),
+                (
+                    3,
+                    Violation::PositionOutOfRange {
+                        position: 0,
+                        length: 9
+                    }
+                ),
+            ]
+        );
+    }
 }
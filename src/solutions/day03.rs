@@ -2,32 +2,137 @@ use anyhow::Context;
 use aoc_runner_derive::{aoc, aoc_generator};
 use memchr::memchr;
 use ndarray::{Array, Array2};
-use std::iter::successors;
+use std::{iter::successors, ops::RangeInclusive};
 
 type GeneratorOutput = Array2<MapElement>;
 type PartInput = GeneratorOutput;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 #[repr(u8)]
 pub enum MapElement {
     Empty = 0,
     Tree = 1,
+    Rock = 2,
+    Snow = 3,
+    Obstacle = 4,
+}
+
+impl MapElement {
+    pub const ALL: [MapElement; 5] = [
+        MapElement::Empty,
+        MapElement::Tree,
+        MapElement::Rock,
+        MapElement::Snow,
+        MapElement::Obstacle,
+    ];
+
+    /// The cost of passing the tile in a new [`TileSet`].
+    pub fn default_cost(self) -> u64 {
+        match self {
+            MapElement::Empty => 0,
+            MapElement::Tree => 1,
+            MapElement::Rock => 1,
+            MapElement::Snow => 0,
+            MapElement::Obstacle => 1,
+        }
+    }
+}
+
+const TILE_KINDS: usize = MapElement::ALL.len();
+
+/// Maps input bytes to tiles and back, and assigns every tile a cost for [`path_cost`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TileSet {
+    tiles: [Option<MapElement>; 256],
+    symbols: [Option<u8>; TILE_KINDS],
+    costs: [u64; TILE_KINDS],
+}
+
+impl TileSet {
+    /// A tile set without any symbols and with the default costs. See [`TileSet::default`] for the
+    /// puzzle's `.` and `#`.
+    pub fn empty() -> Self {
+        TileSet {
+            tiles: [None; 256],
+            symbols: [None; TILE_KINDS],
+            costs: MapElement::ALL.map(MapElement::default_cost),
+        }
+    }
+
+    /// Maps `symbol` to `tile`. The first symbol inserted for a tile is the one it is rendered as.
+    pub fn insert(&mut self, symbol: u8, tile: MapElement) {
+        self.tiles[symbol as usize] = Some(tile);
+        self.symbols[tile as usize].get_or_insert(symbol);
+    }
+
+    pub fn set_cost(&mut self, tile: MapElement, cost: u64) {
+        self.costs[tile as usize] = cost;
+    }
+
+    pub fn tile(&self, symbol: u8) -> Option<MapElement> {
+        self.tiles[symbol as usize]
+    }
+
+    pub fn symbol(&self, tile: MapElement) -> Option<u8> {
+        self.symbols[tile as usize]
+    }
+
+    pub fn cost(&self, tile: MapElement) -> u64 {
+        self.costs[tile as usize]
+    }
+}
+
+impl Default for TileSet {
+    fn default() -> Self {
+        let mut tiles = TileSet::empty();
+        tiles.insert(b'.', MapElement::Empty);
+        tiles.insert(b'#', MapElement::Tree);
+        tiles
+    }
 }
 
 #[aoc_generator(day3)]
 pub fn generator(input: &[u8]) -> anyhow::Result<GeneratorOutput> {
-    let height = bytecount::count(input, b'\n') + 1;
-    let width = memchr(b'\n', input).unwrap();
-    input
-        .iter()
-        .filter_map(|&c| match c {
-            b'.' => Some(MapElement::Empty),
-            b'#' => Some(MapElement::Tree),
-            _ => None,
-        })
-        .collect::<Array<_, _>>()
-        .into_shape([height, width])
-        .context("reshape failed")
+    parse_map(input, &TileSet::default())
+}
+
+pub fn parse_map(input: &[u8], tiles: &TileSet) -> anyhow::Result<Array2<MapElement>> {
+    let input = input.strip_suffix(b"\n").unwrap_or(input);
+    let width = memchr(b'\n', input).unwrap_or(input.len());
+    let width = match input[..width].last() {
+        Some(b'\r') => width - 1,
+        _ => width,
+    };
+    if width == 0 {
+        anyhow::bail!("the map is empty or starts with a blank row");
+    }
+    let mut height = 0;
+    let mut elements = Vec::with_capacity(input.len());
+
+    for (row, line) in input.split(|&c| c == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.len() != width {
+            anyhow::bail!(
+                "row {} has length {}, expected {}",
+                row + 1,
+                line.len(),
+                width
+            );
+        }
+        for (column, &symbol) in line.iter().enumerate() {
+            elements.push(tiles.tile(symbol).with_context(|| {
+                format!(
+                    "unknown tile {:?} at row {}, column {}",
+                    symbol as char,
+                    row + 1,
+                    column + 1
+                )
+            })?);
+        }
+        height += 1;
+    }
+
+    Array::from_shape_vec([height, width], elements).context("reshape failed")
 }
 
 #[aoc(day3, part1)]
@@ -35,26 +140,181 @@ pub fn part_1(input: &PartInput) -> usize {
     count_trees(input, 1, 3)
 }
 
-pub fn count_trees(map: &Array2<MapElement>, down: usize, right: usize) -> usize {
+/// The coordinates visited when moving along a slope from the top left until past the bottom.
+/// Empty for `down == 0`, which would never leave the first row; [`count_trees_many`] and the
+/// renderers treat such slopes the same way.
+pub fn path(
+    map: &Array2<MapElement>,
+    down: usize,
+    right: isize,
+) -> impl Iterator<Item = (usize, usize)> {
     let height = map.nrows();
-    let width = map.ncols();
+    let width = map.ncols() as isize;
 
-    successors(Some((0, 0)), |&(y, x)| match y + down {
-        y if y < height => Some((y, (x + right) % width)),
+    let start = (down > 0).then_some((0, 0));
+    successors(start, move |&(y, x)| match y + down {
+        y if y < height => Some((y, (x as isize + right).rem_euclid(width) as usize)),
         _ => None,
     })
-    .map(|c| map[c] as usize)
-    .sum()
 }
 
+pub fn count_trees(map: &Array2<MapElement>, down: usize, right: isize) -> usize {
+    path(map, down, right)
+        .filter(|&c| map[c] == MapElement::Tree)
+        .count()
+}
+
+pub fn path_cost(map: &Array2<MapElement>, tiles: &TileSet, down: usize, right: isize) -> u64 {
+    path(map, down, right).map(|c| tiles.cost(map[c])).sum()
+}
+
+impl MapElement {
+    fn color(self) -> [u8; 3] {
+        match self {
+            MapElement::Empty => [255, 255, 255],
+            MapElement::Tree => [34, 139, 34],
+            MapElement::Rock => [128, 128, 128],
+            MapElement::Snow => [200, 220, 255],
+            MapElement::Obstacle => [139, 69, 19],
+        }
+    }
+}
+
+/// Walks over the map repeated horizontally as often as the slope needs, yielding every cell as
+/// `(tile, visited)` row by row, together with the rendered width.
+fn overlay(
+    map: &Array2<MapElement>,
+    down: usize,
+    right: isize,
+) -> (usize, impl Iterator<Item = (MapElement, bool)> + '_) {
+    let width = map.ncols() as isize;
+    let last_step = ((map.nrows().max(1) - 1) / down.max(1)) as isize;
+    let end_x = last_step * right;
+    let first_copy = end_x.min(0).div_euclid(width);
+    let last_copy = end_x.max(0).div_euclid(width);
+    let columns = (first_copy * width)..((last_copy + 1) * width);
+    let rendered_width = columns.len();
+
+    let cells = map.outer_iter().enumerate().flat_map(move |(y, row)| {
+        let visited_x = if down > 0 && y % down == 0 {
+            Some((y / down) as isize * right)
+        } else {
+            None
+        };
+        columns
+            .clone()
+            .map(move |x| (row[x.rem_euclid(width) as usize], visited_x == Some(x)))
+    });
+    (rendered_width, cells)
+}
+
+/// Draws the map with the symbols of `tiles` and every cell on the slope marked, `X` for trees and
+/// `O` for everything else. Tiles without a symbol are drawn as `?`.
+pub fn render_path(map: &Array2<MapElement>, tiles: &TileSet, down: usize, right: isize) -> String {
+    let (width, cells) = overlay(map, down, right);
+    let mut output = String::with_capacity(map.nrows() * (width + 1));
+    for (idx, (tile, visited)) in cells.enumerate() {
+        if idx > 0 && idx % width == 0 {
+            output.push('\n');
+        }
+        output.push(match (tile, visited) {
+            (MapElement::Tree, true) => 'X',
+            (_, true) => 'O',
+            (tile, false) => tiles.symbol(tile).map_or('?', char::from),
+        });
+    }
+    output
+}
+
+/// Draws the same overlay as [`render_path`] as a binary PPM image with one pixel per cell.
+pub fn render_path_ppm(map: &Array2<MapElement>, down: usize, right: isize) -> Vec<u8> {
+    let (width, cells) = overlay(map, down, right);
+    let mut output = format!("P6\n{} {}\n255\n", width, map.nrows()).into_bytes();
+    output.reserve(width * map.nrows() * 3);
+    for (tile, visited) in cells {
+        output.extend_from_slice(&match (tile, visited) {
+            (MapElement::Tree, true) => [220, 0, 0],
+            (_, true) => [255, 200, 0],
+            (tile, false) => tile.color(),
+        });
+    }
+    output
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Slope {
+    pub down: usize,
+    pub right: isize,
+}
+
+impl Slope {
+    pub fn new(down: usize, right: isize) -> Self {
+        Slope { down, right }
+    }
+}
+
+pub fn slopes_in_range(downs: RangeInclusive<usize>, rights: RangeInclusive<isize>) -> Vec<Slope> {
+    downs
+        .filter(|&down| down > 0)
+        .flat_map(|down| rights.clone().map(move |right| Slope::new(down, right)))
+        .collect()
+}
+
+/// Counts the trees hit by each slope, visiting every row of the map only once.
+pub fn count_trees_many(map: &Array2<MapElement>, slopes: &[Slope]) -> Vec<usize> {
+    let width = map.ncols() as isize;
+    let mut counts = vec![0; slopes.len()];
+
+    for (y, row) in map.outer_iter().enumerate() {
+        for (count, slope) in counts.iter_mut().zip(slopes) {
+            if slope.down == 0 || y % slope.down != 0 {
+                continue;
+            }
+            let step = (y / slope.down) as isize;
+            let x = (step * slope.right.rem_euclid(width)).rem_euclid(width);
+            *count += (row[x as usize] == MapElement::Tree) as usize;
+        }
+    }
+    counts
+}
+
+/// Finds the slope in the given ranges that hits the fewest trees, preferring earlier slopes on
+/// ties.
+pub fn best_slope(
+    map: &Array2<MapElement>,
+    downs: RangeInclusive<usize>,
+    rights: RangeInclusive<isize>,
+) -> Option<(Slope, usize)> {
+    let slopes = slopes_in_range(downs, rights);
+    let counts = count_trees_many(map, &slopes);
+    slopes
+        .into_iter()
+        .zip(counts)
+        .min_by_key(|&(_, count)| count)
+}
+
+const PART_2_SLOPES: [(usize, isize); 5] = [(1, 1), (1, 3), (1, 5), (1, 7), (2, 1)];
+
 #[aoc(day3, part2)]
 pub fn part_2(input: &PartInput) -> u128 {
-    [(1, 1), (1, 3), (1, 5), (1, 7), (2, 1)]
+    PART_2_SLOPES
         .iter()
         .map(|&(down, right)| count_trees(input, down, right) as u128)
         .product()
 }
 
+#[aoc(day3, part2, one_pass)]
+pub fn part_2_one_pass(input: &PartInput) -> u128 {
+    let slopes = PART_2_SLOPES
+        .iter()
+        .map(|&(down, right)| Slope::new(down, right))
+        .collect::<Vec<_>>();
+    count_trees_many(input, &slopes)
+        .into_iter()
+        .map(|count| count as u128)
+        .product()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +339,88 @@ mod tests {
     fn part_2_test() {
         assert_eq!(part_2(&generator(MAP).unwrap()), 336);
     }
+
+    #[test]
+    fn part_2_one_pass_test() {
+        assert_eq!(part_2_one_pass(&generator(MAP).unwrap()), 336);
+    }
+
+    #[test]
+    fn slope_search_test() {
+        let map = generator(MAP).unwrap();
+        let slopes = slopes_in_range(0..=3, -12..=12);
+        let counts = count_trees_many(&map, &slopes);
+        for (slope, count) in slopes.iter().zip(&counts) {
+            assert_eq!(count_trees(&map, slope.down, slope.right), *count);
+        }
+        assert_eq!(count_trees(&map, 1, -8), count_trees(&map, 1, 3));
+
+        let (slope, count) = best_slope(&map, 1..=2, -3..=3).unwrap();
+        assert_eq!(
+            count,
+            slopes_in_range(1..=2, -3..=3)
+                .iter()
+                .map(|slope| count_trees(&map, slope.down, slope.right))
+                .min()
+                .unwrap()
+        );
+        assert_eq!(count_trees(&map, slope.down, slope.right), count);
+        assert!(best_slope(&map, 0..=0, 1..=3).is_none());
+        assert_eq!(path(&map, 0, 3).count(), 0);
+        assert_eq!(count_trees(&map, 0, 3), 0);
+        assert_eq!(path_cost(&map, &TileSet::default(), 0, 3), 0);
+        assert_eq!(count_trees_many(&map, &[Slope::new(0, 3)]), vec![0]);
+    }
+
+    #[test]
+    fn parse_map_test() {
+        let mut tiles = TileSet::default();
+        tiles.insert(b'^', MapElement::Rock);
+        tiles.insert(b'*', MapElement::Snow);
+        tiles.set_cost(MapElement::Rock, 10);
+        tiles.set_cost(MapElement::Snow, 2);
+
+        let map = parse_map(b".^*\r\n#..\r\n.*^\r\n", &tiles).unwrap();
+        assert_eq!(map.dim(), (3, 3));
+        assert_eq!(map[[0, 1]], MapElement::Rock);
+        assert_eq!(count_trees(&map, 1, 1), 0);
+        assert_eq!(path_cost(&map, &tiles, 1, 1), 10);
+        assert_eq!(path_cost(&map, &tiles, 1, -1), 2);
+
+        let error = parse_map(b"..\n.x", &tiles).unwrap_err();
+        assert_eq!(error.to_string(), "unknown tile 'x' at row 2, column 2");
+        assert!(parse_map(b"...\n..", &tiles).is_err());
+        assert!(generator(b"").is_err());
+        assert!(parse_map(b"\n", &tiles).is_err());
+        assert!(parse_map(b"\r\n..", &tiles).is_err());
+    }
+
+    #[test]
+    fn render_path_test() {
+        let tiles = TileSet::default();
+        let map = generator(b"..#\n#..\n.#.\n..#").unwrap();
+        assert_eq!(
+            render_path(&map, &tiles, 1, 2),
+            "O.#..#..#\n#.O#..#..\n.#..X..#.\n..#..#O.#"
+        );
+        assert_eq!(
+            render_path(&map, &tiles, 2, -1),
+            "..#O.#\n#..#..\n.#O.#.\n..#..#"
+        );
+
+        let mut tiles = TileSet::empty();
+        tiles.insert(b'_', MapElement::Empty);
+        tiles.insert(b'T', MapElement::Tree);
+        tiles.insert(b'^', MapElement::Rock);
+        tiles.insert(b'A', MapElement::Rock);
+        let mut custom = parse_map(b"_T^\nA__", &tiles).unwrap();
+        assert_eq!(render_path(&custom, &tiles, 2, 1), "OT^\n^__");
+        custom[[1, 1]] = MapElement::Snow;
+        assert_eq!(render_path(&custom, &tiles, 2, 1), "OT^\n^?_");
+
+        let image = render_path_ppm(&map, 1, 2);
+        let header = b"P6\n9 4\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 9 * 4 * 3);
+    }
 }
@@ -1,13 +1,25 @@
+use anyhow::Context;
 use aoc_runner_derive::{aoc, aoc_generator};
 use nom::{AsChar, IResult};
-use std::collections::HashMap;
+use std::{
+    borrow::Cow, collections::HashMap, ops::RangeInclusive, path::Path, str::FromStr,
+    sync::OnceLock,
+};
 
-type Passport = HashMap<String, String>;
-type GeneratorOutput = Vec<Passport>;
-type PartInput = [Passport];
+type GeneratorOutput = Vec<Passport<'static>>;
+type PartInput = [Passport<'static>];
 
+/// The runner keeps the generator output apart from the input, so these passports own their text.
+/// Use [`parse_passports`] to borrow from the input instead.
 #[aoc_generator(day4)]
 pub fn generator(input: &str) -> anyhow::Result<GeneratorOutput> {
+    Ok(parse_passports(input)?
+        .into_iter()
+        .map(Passport::into_owned)
+        .collect())
+}
+
+pub fn parse_passports(input: &str) -> anyhow::Result<Vec<Passport<'_>>> {
     use nom::{
         branch::alt,
         bytes::complete::tag,
@@ -18,28 +30,352 @@ pub fn generator(input: &str) -> anyhow::Result<GeneratorOutput> {
         tag("\n\n"),
         map(
             separated_list1(alt((tag(" "), tag("\n"))), parse_field),
-            |list| list.into_iter().collect(),
+            Passport::from_fields,
         ),
     ))(input)
     .map_err(|e| e.map(|e| (e.input, e.code)).to_owned())?
     .1)
 }
 
-fn parse_field(input: &str) -> IResult<&str, (String, String)> {
+fn parse_field(input: &str) -> IResult<&str, (&str, &str)> {
     use nom::bytes::complete::{tag, take_while};
     let (input, key) = take_while(|c: char| c.is_ascii_alphabetic())(input)?;
     let (input, _) = tag(":")(input)?;
     let (input, value) = take_while(|c: char| !c.is_ascii_whitespace())(input)?;
 
-    Ok((input, (key.to_owned(), value.to_owned())))
+    Ok((input, (key, value)))
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BirthYear(pub u32);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct IssueYear(pub u32);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ExpirationYear(pub u32);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Height {
+    pub value: u32,
+    pub unit: HeightUnit,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HairColor(pub u8, pub u8, pub u8);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum EyeColor {
+    Amber,
+    Blue,
+    Brown,
+    Gray,
+    Green,
+    Hazel,
+    Other,
+}
+
+impl EyeColor {
+    fn parse(input: &str) -> Option<Self> {
+        Some(match input {
+            "amb" => EyeColor::Amber,
+            "blu" => EyeColor::Blue,
+            "brn" => EyeColor::Brown,
+            "gry" => EyeColor::Gray,
+            "grn" => EyeColor::Green,
+            "hzl" => EyeColor::Hazel,
+            "oth" => EyeColor::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// A passport number made up of ASCII digits, kept as text to preserve leading zeros.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PassportId<'a>(pub Cow<'a, str>);
+
+/// What a field value parses as, independent of its key. Computed once per field so that
+/// validation does not have to parse the text again.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ParsedValue {
+    Number(u32),
+    Height(Height),
+    Color(HairColor),
+    Other,
+}
+
+impl ParsedValue {
+    pub fn parse(value: &str) -> Self {
+        use nom::combinator::all_consuming;
+        if let Ok(number) = value.parse() {
+            ParsedValue::Number(number)
+        } else if let Ok((_, (value, unit))) = all_consuming(parse_height)(value) {
+            ParsedValue::Height(Height { value, unit })
+        } else if let Ok((_, (r, g, b))) = all_consuming(parse_color_code)(value) {
+            ParsedValue::Color(HairColor(r, g, b))
+        } else {
+            ParsedValue::Other
+        }
+    }
+}
+
+/// A passport borrowing from its input. The typed getters return `None` when the field is missing
+/// or does not parse; the raw text of every field, including unknown ones, stays available.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Passport<'a> {
+    birth_year: Option<BirthYear>,
+    issue_year: Option<IssueYear>,
+    expiration_year: Option<ExpirationYear>,
+    height: Option<Height>,
+    hair_color: Option<HairColor>,
+    eye_color: Option<EyeColor>,
+    passport_id: Option<PassportId<'a>>,
+    country_id: Option<Cow<'a, str>>,
+    fields: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    parsed: Vec<ParsedValue>,
+    /// Positions in `fields` sorted by key, keeping only the last occurrence of each key.
+    index: Vec<usize>,
+}
+
+impl<'a> Passport<'a> {
+    pub fn from_fields(fields: Vec<(&'a str, &'a str)>) -> Self {
+        let mut passport = Passport::default();
+        for &(key, value) in &fields {
+            let parsed = ParsedValue::parse(value);
+            let number = match parsed {
+                ParsedValue::Number(number) => Some(number),
+                _ => None,
+            };
+            match key {
+                "byr" => passport.birth_year = number.map(BirthYear),
+                "iyr" => passport.issue_year = number.map(IssueYear),
+                "eyr" => passport.expiration_year = number.map(ExpirationYear),
+                "hgt" => {
+                    passport.height = match parsed {
+                        ParsedValue::Height(height) => Some(height),
+                        _ => None,
+                    }
+                }
+                "hcl" => {
+                    passport.hair_color = match parsed {
+                        ParsedValue::Color(color) => Some(color),
+                        _ => None,
+                    }
+                }
+                "ecl" => passport.eye_color = EyeColor::parse(value),
+                "pid" => {
+                    passport.passport_id = Some(value)
+                        .filter(|pid| !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit()))
+                        .map(|pid| PassportId(pid.into()))
+                }
+                "cid" => passport.country_id = Some(value.into()),
+                _ => {}
+            }
+            passport.parsed.push(parsed);
+        }
+        passport.index = (0..fields.len()).rev().collect();
+        passport.index.sort_by_key(|&idx| fields[idx].0);
+        passport.index.dedup_by_key(|&mut idx| fields[idx].0);
+        passport.fields = fields
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+        passport
+    }
+
+    /// Copies all borrowed text so that the passport no longer depends on its input.
+    pub fn into_owned(self) -> Passport<'static> {
+        fn owned(s: Cow<'_, str>) -> Cow<'static, str> {
+            Cow::Owned(s.into_owned())
+        }
+
+        Passport {
+            birth_year: self.birth_year,
+            issue_year: self.issue_year,
+            expiration_year: self.expiration_year,
+            height: self.height,
+            hair_color: self.hair_color,
+            eye_color: self.eye_color,
+            passport_id: self
+                .passport_id
+                .map(|PassportId(pid)| PassportId(owned(pid))),
+            country_id: self.country_id.map(owned),
+            fields: self
+                .fields
+                .into_iter()
+                .map(|(key, value)| (owned(key), owned(value)))
+                .collect(),
+            parsed: self.parsed,
+            index: self.index,
+        }
+    }
+
+    pub fn birth_year(&self) -> Option<BirthYear> {
+        self.birth_year
+    }
+
+    pub fn issue_year(&self) -> Option<IssueYear> {
+        self.issue_year
+    }
+
+    pub fn expiration_year(&self) -> Option<ExpirationYear> {
+        self.expiration_year
+    }
+
+    pub fn height(&self) -> Option<Height> {
+        self.height
+    }
+
+    pub fn hair_color(&self) -> Option<HairColor> {
+        self.hair_color
+    }
+
+    pub fn eye_color(&self) -> Option<EyeColor> {
+        self.eye_color
+    }
+
+    pub fn passport_id(&self) -> Option<&PassportId<'a>> {
+        self.passport_id.as_ref()
+    }
+
+    pub fn country_id(&self) -> Option<&str> {
+        self.country_id.as_deref()
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.index
+            .binary_search_by(|&idx| (*self.fields[idx].0).cmp(key))
+            .ok()
+            .map(|found| self.index[found])
+    }
+
+    /// The raw value of a field. Later occurrences of a key win over earlier ones.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.position(key).map(|idx| &*self.fields[idx].1)
+    }
+
+    /// The raw value of a field together with what it parses as.
+    pub fn get_parsed(&self, key: &str) -> Option<(&str, ParsedValue)> {
+        self.position(key)
+            .map(|idx| (&*self.fields[idx].1, self.parsed[idx]))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// All fields in input order.
+    pub fn fields(&self) -> impl DoubleEndedIterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(key, value)| (&**key, &**value))
+    }
+
+    /// The fields without a rule in the [`default_schema`].
+    pub fn unknown_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        let schema = default_schema();
+        self.fields()
+            .filter(move |(key, _)| schema.rule(key).is_none())
+    }
+
+    /// The fields sorted by key, keeping only the value that [`Passport::get`] would return.
+    pub fn canonical_fields(&self) -> Vec<(&str, &str)> {
+        self.index
+            .iter()
+            .map(|&idx| (&*self.fields[idx].0, &*self.fields[idx].1))
+            .collect()
+    }
+}
+
+/// Writes passports in the input format, one sorted field per line.
+pub fn to_canonical(passports: &[Passport]) -> String {
+    passports
+        .iter()
+        .map(|passport| {
+            passport
+                .canonical_fields()
+                .iter()
+                .map(|(key, value)| format!("{}:{}", key, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+pub fn to_json(passports: &[Passport]) -> String {
+    fn escape(output: &mut String, s: &str) {
+        output.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => output.push_str("\\\""),
+                '\\' => output.push_str("\\\\"),
+                c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+                c => output.push(c),
+            }
+        }
+        output.push('"');
+    }
+
+    let mut output = String::from("[");
+    for (idx, passport) in passports.iter().enumerate() {
+        if idx > 0 {
+            output.push(',');
+        }
+        output.push('{');
+        for (field_idx, (key, value)) in passport.canonical_fields().into_iter().enumerate() {
+            if field_idx > 0 {
+                output.push(',');
+            }
+            escape(&mut output, key);
+            output.push(':');
+            escape(&mut output, value);
+        }
+        output.push('}');
+    }
+    output.push(']');
+    output
+}
+
+/// Writes one row per passport with a column for every key that occurs in the batch.
+pub fn to_csv(passports: &[Passport]) -> String {
+    fn escape(s: &str) -> String {
+        if s.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_owned()
+        }
+    }
+
+    let mut keys = passports
+        .iter()
+        .flat_map(|passport| passport.fields().map(|(key, _)| key))
+        .collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut output = keys
+        .iter()
+        .map(|key| escape(key))
+        .collect::<Vec<_>>()
+        .join(",");
+    output.push('\n');
+    for passport in passports {
+        output.push_str(
+            &keys
+                .iter()
+                .map(|key| escape(passport.get(key).unwrap_or("")))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        output.push('\n');
+    }
+    output
 }
 
 #[aoc(day4, part1)]
 pub fn part_1(input: &PartInput) -> usize {
-    const REQUIRED_KEYS: [&str; 7] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
     input
         .iter()
-        .filter(|&passport| REQUIRED_KEYS.iter().all(|&key| passport.contains_key(key)))
+        .filter(|&passport| default_schema().has_required_fields(passport))
         .count()
 }
 
@@ -51,49 +387,312 @@ pub fn part_2(input: &PartInput) -> usize {
         .count()
 }
 
-fn is_passport_valid(passport: &Passport) -> bool {
-    match passport.get("byr").and_then(|byr| byr.parse::<u32>().ok()) {
-        Some(birth_year) if (1920..=2002).contains(&birth_year) => {}
-        _ => return false,
+pub fn is_passport_valid(passport: &Passport) -> bool {
+    default_schema().is_valid(passport)
+}
+
+/// The [`DEFAULT_SCHEMA`], parsed once on first use.
+pub fn default_schema() -> &'static Schema {
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(Schema::default)
+}
+
+/// The rules of the puzzle, in the format read by [`Schema::from_str`].
+pub const DEFAULT_SCHEMA: &str = "\
+# key [optional] type [arguments]
+byr year 1920..=2002
+iyr year 2010..=2020
+eyr year 2020..=2030
+hgt height cm 150..=193 in 59..=76
+hcl color
+ecl enum amb blu brn gry grn hzl oth
+pid digits 9
+cid optional any
+";
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FieldType {
+    Year(RangeInclusive<u32>),
+    Height {
+        centimeters: Option<RangeInclusive<u32>>,
+        inches: Option<RangeInclusive<u32>>,
+    },
+    Color,
+    Enum(Vec<String>),
+    Digits(usize),
+    Any,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FieldStatus {
+    Valid,
+    Missing,
+    Unparsable,
+    OutOfRange,
+    InvalidEnum,
+}
+
+impl FieldType {
+    pub fn check(&self, value: &str) -> FieldStatus {
+        self.check_parsed(value, ParsedValue::parse(value))
     }
-    match passport.get("iyr").and_then(|iyr| iyr.parse::<u32>().ok()) {
-        Some(issue_year) if (2010..=2020).contains(&issue_year) => {}
-        _ => return false,
+
+    /// Checks a value that has already been parsed by [`ParsedValue::parse`].
+    pub fn check_parsed(&self, value: &str, parsed: ParsedValue) -> FieldStatus {
+        let in_range = |range: Option<&RangeInclusive<u32>>, x| match range {
+            Some(range) if range.contains(&x) => FieldStatus::Valid,
+            _ => FieldStatus::OutOfRange,
+        };
+        match self {
+            FieldType::Year(range) => match parsed {
+                ParsedValue::Number(year) => in_range(Some(range), year),
+                _ => FieldStatus::Unparsable,
+            },
+            FieldType::Height {
+                centimeters,
+                inches,
+            } => match parsed {
+                ParsedValue::Height(Height {
+                    value,
+                    unit: HeightUnit::Centimeters,
+                }) => in_range(centimeters.as_ref(), value),
+                ParsedValue::Height(Height {
+                    value,
+                    unit: HeightUnit::Inches,
+                }) => in_range(inches.as_ref(), value),
+                _ => FieldStatus::Unparsable,
+            },
+            FieldType::Color => match parsed {
+                ParsedValue::Color(_) => FieldStatus::Valid,
+                _ => FieldStatus::Unparsable,
+            },
+            FieldType::Enum(values) if values.iter().any(|v| v == value) => FieldStatus::Valid,
+            FieldType::Enum(_) => FieldStatus::InvalidEnum,
+            FieldType::Digits(length)
+                if value.len() == *length && value.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                FieldStatus::Valid
+            }
+            FieldType::Digits(_) => FieldStatus::Unparsable,
+            FieldType::Any => FieldStatus::Valid,
+        }
     }
-    match passport.get("eyr").and_then(|eyr| eyr.parse::<u32>().ok()) {
-        Some(expiration_year) if (2020..=2030).contains(&expiration_year) => {}
-        _ => return false,
+
+    pub fn matches(&self, value: &str) -> bool {
+        self.check(value) == FieldStatus::Valid
     }
-    match passport
-        .get("hgt")
-        .and_then(|hgt| parse_height(hgt).ok())
-        .map(|(_, v)| v)
-    {
-        Some((height, HeightUnit::Centimeters)) if (150..=193).contains(&height) => {}
-        Some((height, HeightUnit::Inches)) if (59..=76).contains(&height) => {}
-        _ => return false,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FieldRule {
+    pub key: String,
+    pub required: bool,
+    pub field_type: FieldType,
+}
+
+impl FieldRule {
+    pub fn check(&self, passport: &Passport) -> FieldStatus {
+        match passport.get_parsed(&self.key) {
+            Some((value, parsed)) => self.field_type.check_parsed(value, parsed),
+            None if self.required => FieldStatus::Missing,
+            None => FieldStatus::Valid,
+        }
     }
-    match passport
-        .get("hcl")
-        .and_then(|hcl| parse_color_code(hcl).ok())
-    {
-        Some(_) => {}
-        _ => return false,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Schema {
+    pub fields: Vec<FieldRule>,
+}
+
+impl Schema {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .with_context(|| format!("could not read schema {}", path.display()))?
+            .parse()
+    }
+
+    pub fn rule(&self, key: &str) -> Option<&FieldRule> {
+        self.fields.iter().find(|rule| rule.key == key)
+    }
+
+    pub fn required_keys(&self) -> impl Iterator<Item = &str> {
+        self.fields
+            .iter()
+            .filter(|rule| rule.required)
+            .map(|rule| rule.key.as_str())
+    }
+
+    /// Every required field is present, whatever its value.
+    pub fn has_required_fields(&self, passport: &Passport) -> bool {
+        self.required_keys().all(|key| passport.contains_key(key))
     }
-    const VALID_COLORS: [&str; 7] = ["amb", "blu", "brn", "gry", "grn", "hzl", "oth"];
-    match passport.get("ecl") {
-        Some(eye_color) if VALID_COLORS.contains(&eye_color.as_str()) => {}
-        _ => return false,
+
+    /// Every required field is present and every present field follows its rule. Fields unknown
+    /// to the schema are ignored.
+    pub fn is_valid(&self, passport: &Passport) -> bool {
+        self.fields
+            .iter()
+            .all(|rule| rule.check(passport) == FieldStatus::Valid)
     }
-    match passport.get("pid") {
-        Some(pid) if pid.len() == 9 && pid.chars().all(|c| c.is_ascii_digit()) => {}
-        _ => return false,
+
+    pub fn report<'a>(&'a self, index: usize, passport: &'a Passport<'_>) -> PassportReport<'a> {
+        PassportReport {
+            index,
+            fields: self
+                .fields
+                .iter()
+                .map(|rule| FieldReport {
+                    key: &rule.key,
+                    value: passport.get(&rule.key),
+                    status: rule.check(passport),
+                })
+                .collect(),
+        }
     }
-    true
+
+    pub fn report_batch<'a>(&'a self, passports: &'a [Passport<'_>]) -> Vec<PassportReport<'a>> {
+        passports
+            .iter()
+            .enumerate()
+            .map(|(index, passport)| self.report(index, passport))
+            .collect()
+    }
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        DEFAULT_SCHEMA.parse().unwrap()
+    }
+}
+
+impl FromStr for Schema {
+    type Err = anyhow::Error;
+
+    /// Reads one rule per line as `key [optional] type [arguments]`, where type is one of
+    /// `year MIN..=MAX`, `height [cm MIN..=MAX] [in MIN..=MAX]`, `color`, `enum VALUE...`,
+    /// `digits LENGTH` or `any`. Blank lines and lines starting with `#` are skipped.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        fn range(word: Option<&str>) -> anyhow::Result<RangeInclusive<u32>> {
+            let word = word.context("missing range")?;
+            let (min, max) = word
+                .split_once("..=")
+                .with_context(|| format!("expected MIN..=MAX, found {:?}", word))?;
+            Ok(min.parse()?..=max.parse()?)
+        }
+
+        let mut fields = Vec::new();
+        for (line_idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = (|| {
+                let mut words = line.split_whitespace();
+                let key = words.next().context("missing key")?.to_owned();
+                let mut kind = words.next().context("missing type")?;
+                let required = kind != "optional";
+                if !required {
+                    kind = words.next().context("missing type")?;
+                }
+                let field_type = match kind {
+                    "year" => FieldType::Year(range(words.next())?),
+                    "height" => {
+                        let mut centimeters = None;
+                        let mut inches = None;
+                        while let Some(unit) = words.next() {
+                            match unit {
+                                "cm" => centimeters = Some(range(words.next())?),
+                                "in" => inches = Some(range(words.next())?),
+                                _ => anyhow::bail!("unknown height unit {:?}", unit),
+                            }
+                        }
+                        FieldType::Height {
+                            centimeters,
+                            inches,
+                        }
+                    }
+                    "color" => FieldType::Color,
+                    "enum" => FieldType::Enum(words.by_ref().map(str::to_owned).collect()),
+                    "digits" => FieldType::Digits(words.next().context("missing length")?.parse()?),
+                    "any" => FieldType::Any,
+                    _ => anyhow::bail!("unknown field type {:?}", kind),
+                };
+                if let Some(word) = words.next() {
+                    anyhow::bail!("unexpected {:?}", word);
+                }
+                Ok(FieldRule {
+                    key,
+                    required,
+                    field_type,
+                })
+            })()
+            .with_context(|| format!("invalid schema rule on line {}", line_idx + 1))?;
+            fields.push(rule);
+        }
+        Ok(Schema { fields })
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FieldReport<'a> {
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+    pub status: FieldStatus,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum HeightUnit {
+/// The status of every field of the schema for the passport at `index` in its batch.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PassportReport<'a> {
+    pub index: usize,
+    pub fields: Vec<FieldReport<'a>>,
+}
+
+impl PassportReport<'_> {
+    pub fn is_valid(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|field| field.status == FieldStatus::Valid)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &FieldReport<'_>> {
+        self.fields
+            .iter()
+            .filter(|field| field.status != FieldStatus::Valid)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ReportStatistics {
+    pub passports: usize,
+    pub valid: usize,
+    pub failures: HashMap<FieldStatus, usize>,
+    pub failures_by_key: HashMap<(String, FieldStatus), usize>,
+}
+
+impl ReportStatistics {
+    pub fn new(reports: &[PassportReport]) -> Self {
+        let mut statistics = ReportStatistics {
+            passports: reports.len(),
+            ..Default::default()
+        };
+        for report in reports {
+            statistics.valid += report.is_valid() as usize;
+            for field in report.failures() {
+                *statistics.failures.entry(field.status).or_default() += 1;
+                *statistics
+                    .failures_by_key
+                    .entry((field.key.to_owned(), field.status))
+                    .or_default() += 1;
+            }
+        }
+        statistics
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum HeightUnit {
     Centimeters,
     Inches,
 }
@@ -101,12 +700,11 @@ enum HeightUnit {
 fn parse_height(input: &str) -> IResult<&str, (u32, HeightUnit)> {
     use nom::{
         branch::alt,
-        bytes::complete::{tag, take_while},
-        combinator::{map, value},
+        bytes::complete::tag,
+        character::complete::digit1,
+        combinator::{map_res, value},
     };
-    let (input, number) = map(take_while(|c: char| c.is_ascii_digit()), |s: &str| {
-        s.parse::<u32>().unwrap()
-    })(input)?;
+    let (input, number) = map_res(digit1, |s: &str| s.parse::<u32>())(input)?;
     let (input, unit) = alt((
         value(HeightUnit::Centimeters, tag("cm")),
         value(HeightUnit::Inches, tag("in")),
@@ -136,6 +734,7 @@ fn parse_color_code(input: &str) -> IResult<&str, (u8, u8, u8)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::xorshift::XorShift;
 
     #[test]
     fn part_1_test() {
@@ -206,4 +805,200 @@ iyr:2010 hgt:158cm hcl:#b6652a ecl:blu byr:1944 eyr:2021 pid:093154719"
             4
         );
     }
+
+    #[test]
+    fn schema_test() {
+        let schema = Schema::default();
+        assert_eq!(schema.fields.len(), 8);
+        assert!(FieldType::Year(2000..=2010).matches("2002"));
+        assert!(!FieldType::Year(2000..=2010).matches("2012"));
+        assert!(schema.fields[3].field_type.matches("60in"));
+        assert!(!schema.fields[3].field_type.matches("190in"));
+        assert!(!schema.fields[3].field_type.matches("190"));
+        assert!(!schema.fields[3].field_type.matches("cm"));
+        assert!(schema.fields[4].field_type.matches("#123abc"));
+        assert!(!schema.fields[4].field_type.matches("#123abz"));
+        assert!(!schema.fields[4].field_type.matches("#123abcd"));
+
+        let custom = "byr year 1900..=2020\nhgt optional height cm 100..=250\n"
+            .parse::<Schema>()
+            .unwrap();
+        let passports =
+            parse_passports("byr:1910 hgt:200cm\n\nbyr:1910 hgt:70in\n\nbyr:1910").unwrap();
+        assert_eq!(
+            passports
+                .iter()
+                .map(|passport| custom.is_valid(passport))
+                .collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
+
+        let error = "byr year 1900..=2020\npid digts 9"
+            .parse::<Schema>()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid schema rule on line 2");
+        assert!("hgt height ft 5..=7".parse::<Schema>().is_err());
+    }
+
+    #[test]
+    fn report_test() {
+        let schema = Schema::default();
+        let passports = parse_passports(
+            "byr:1937 iyr:2017 eyr:2020 hgt:183cm hcl:#fffffd ecl:gry pid:860033327
+
+byr:19x7 iyr:2030 eyr:2020 hgt:183 hcl:#fffffd ecl:xyz",
+        )
+        .unwrap();
+        let reports = schema.report_batch(&passports);
+        assert!(reports[0].is_valid());
+        assert_eq!(reports[1].index, 1);
+        assert_eq!(
+            reports[1]
+                .failures()
+                .map(|field| (field.key, field.value, field.status))
+                .collect::<Vec<_>>(),
+            vec![
+                ("byr", Some("19x7"), FieldStatus::Unparsable),
+                ("iyr", Some("2030"), FieldStatus::OutOfRange),
+                ("hgt", Some("183"), FieldStatus::Unparsable),
+                ("ecl", Some("xyz"), FieldStatus::InvalidEnum),
+                ("pid", None, FieldStatus::Missing),
+            ]
+        );
+
+        let statistics = ReportStatistics::new(&reports);
+        assert_eq!(statistics.passports, 2);
+        assert_eq!(statistics.valid, 1);
+        assert_eq!(statistics.failures[&FieldStatus::Unparsable], 2);
+        assert_eq!(statistics.failures[&FieldStatus::Missing], 1);
+        assert_eq!(
+            statistics.failures_by_key[&("iyr".to_owned(), FieldStatus::OutOfRange)],
+            1
+        );
+    }
+
+    #[test]
+    fn default_schema_test() {
+        let schema = default_schema();
+        assert_eq!(
+            schema.required_keys().collect::<Vec<_>>(),
+            ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"]
+        );
+        assert!(!schema.rule("cid").unwrap().required);
+        assert!(schema.rule("xyz").is_none());
+    }
+
+    #[test]
+    fn typed_passport_test() {
+        let passports =
+            parse_passports("byr:1937 hgt:183cm hcl:#fffffd ecl:gry pid:060033327 xyz:abc\n\nbyr:19x7 hgt:7ft ecl:red pid:12a")
+                .unwrap();
+        let passport = &passports[0];
+        assert_eq!(passport.birth_year(), Some(BirthYear(1937)));
+        assert_eq!(
+            passport.height(),
+            Some(Height {
+                value: 183,
+                unit: HeightUnit::Centimeters
+            })
+        );
+        assert_eq!(passport.hair_color(), Some(HairColor(0xff, 0xff, 0xfd)));
+        assert_eq!(passport.eye_color(), Some(EyeColor::Gray));
+        assert_eq!(
+            passport.passport_id(),
+            Some(&PassportId(Cow::Borrowed("060033327")))
+        );
+        assert_eq!(passport.issue_year(), None);
+        assert_eq!(
+            passport.unknown_fields().collect::<Vec<_>>(),
+            vec![("xyz", "abc")]
+        );
+
+        assert_eq!(
+            passport.get_parsed("hgt"),
+            Some((
+                "183cm",
+                ParsedValue::Height(Height {
+                    value: 183,
+                    unit: HeightUnit::Centimeters
+                })
+            ))
+        );
+        assert_eq!(
+            passport.get_parsed("ecl"),
+            Some(("gry", ParsedValue::Other))
+        );
+        assert_eq!(passport.clone().into_owned(), *passport);
+
+        let passport = &passports[1];
+        assert_eq!(passport.birth_year(), None);
+        assert_eq!(passport.get("byr"), Some("19x7"));
+        assert_eq!(passport.height(), None);
+        assert_eq!(passport.eye_color(), None);
+        assert_eq!(passport.passport_id(), None);
+    }
+
+    #[test]
+    fn serialization_test() {
+        let passports =
+            parse_passports("pid:012 byr:1937 xyz:a\"b\nbyr:1938\n\nhcl:#fffffd").unwrap();
+        assert_eq!(
+            to_canonical(&passports),
+            "byr:1938\npid:012\nxyz:a\"b\n\nhcl:#fffffd"
+        );
+        assert_eq!(
+            to_json(&passports),
+            r##"[{"byr":"1938","pid":"012","xyz":"a\"b"},{"hcl":"#fffffd"}]"##
+        );
+        assert_eq!(
+            to_csv(&passports),
+            "byr,hcl,pid,xyz\n1938,,012,\"a\"\"b\"\n,#fffffd,,\n"
+        );
+
+        let survivors = parse_passports(
+            "byr:1937 iyr:2017 eyr:2020 hgt:183cm hcl:#fffffd ecl:gry pid:860033327
+
+byr:1937 iyr:2017",
+        )
+        .unwrap()
+        .into_iter()
+        .filter(is_passport_valid)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            to_canonical(&survivors),
+            "byr:1937\necl:gry\neyr:2020\nhcl:#fffffd\nhgt:183cm\niyr:2017\npid:860033327"
+        );
+    }
+
+    #[test]
+    fn canonical_round_trip_test() {
+        const KEYS: [&str; 10] = [
+            "byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid", "cid", "abc", "zz",
+        ];
+        const VALUES: [&str; 8] = ["1937", "183cm", "#fffffd", "gry", "", "a:b", "#", "12in"];
+        let mut rng = XorShift::new(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..200 {
+            let input = (0..rng.below(4) + 1)
+                .map(|_| {
+                    (0..rng.below(10) + 1)
+                        .map(|_| format!("{}:{}", rng.pick(&KEYS), rng.pick(&VALUES)))
+                        .collect::<Vec<_>>()
+                        .join(if rng.below(2) == 0 { " " } else { "\n" })
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            let passports = parse_passports(&input).unwrap();
+            let canonical = to_canonical(&passports);
+            let reparsed = parse_passports(&canonical).unwrap();
+            assert_eq!(reparsed.len(), passports.len());
+            for (original, reparsed) in passports.iter().zip(&reparsed) {
+                assert_eq!(original.canonical_fields(), reparsed.canonical_fields());
+                assert_eq!(original.birth_year(), reparsed.birth_year());
+                assert_eq!(original.height(), reparsed.height());
+                assert_eq!(is_passport_valid(original), is_passport_valid(reparsed));
+            }
+            assert_eq!(to_canonical(&reparsed), canonical);
+        }
+    }
 }
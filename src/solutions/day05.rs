@@ -1,44 +1,376 @@
+use anyhow::Context;
 use aoc_runner_derive::aoc;
-use std::hint::unreachable_unchecked;
+use fixedbitset::FixedBitSet;
+use std::{
+    convert::{TryFrom, TryInto},
+    ops::Range,
+};
 
 #[aoc(day5, part1)]
-pub fn part_1(input: &[u8]) -> u16 {
-    parse_input(input)
+pub fn part_1(input: &[u8]) -> anyhow::Result<u16> {
+    parse_input(input)?
+        .into_iter()
         .max()
-        .unwrap_or_else(|| unsafe { unreachable_unchecked() })
+        .context("no boarding passes")
 }
 
 #[aoc(day5, part2)]
-pub fn part_2(input: &[u8]) -> u32 {
+pub fn part_2(input: &[u8]) -> anyhow::Result<u32> {
+    let ids = parse_input(input)?;
+    anyhow::ensure!(!ids.is_empty(), "no boarding passes");
     let mut min = 1024;
     let mut max = 0;
     let mut sum = 0;
 
-    for id in parse_input(input) {
+    for id in ids {
         min = min.min(id);
         max = max.max(id);
         sum += id as u32;
     }
 
-    range_sum(min as u32, max as u32) - sum
+    Ok(range_sum(min as u32, max as u32) - sum)
 }
 
-fn parse_input(input: &[u8]) -> impl Iterator<Item = u16> + '_ {
+#[aoc(day5, part2, gaps)]
+pub fn part_2_gaps(input: &[u8]) -> anyhow::Result<u32> {
+    let layout = PlaneLayout::default();
+    let gaps = find_gaps(&parse_input_with(input, layout)?, layout)?;
+    match gaps.missing[..] {
+        [seat] => Ok(seat),
+        _ => anyhow::bail!("expected exactly one free seat, found {:?}", gaps.missing),
+    }
+}
+
+/// Decodes one boarding pass per line, accepting `\n` and `\r\n` line endings with or without a
+/// final line break.
+pub fn parse_input(input: &[u8]) -> anyhow::Result<Vec<u16>> {
+    parse_lines(input, |line| {
+        match <&[u8; 10]>::try_from(line)
+            .ok()
+            .filter(|seat| is_valid_fast(seat))
+        {
+            Some(seat) => Ok(id_fast(seat)),
+            None => Err(PlaneLayout::default()
+                .validate(line)
+                .expect_err("the fast path rejected a valid boarding pass")),
+        }
+    })
+}
+
+/// Like [`parse_input`], for planes with a different number of rows or columns.
+pub fn parse_input_with(input: &[u8], layout: PlaneLayout) -> anyhow::Result<Vec<u32>> {
+    parse_lines(input, |line| {
+        layout.validate(line)?;
+        Ok(layout.decode(line))
+    })
+}
+
+fn parse_lines<T>(
+    input: &[u8],
+    parse_line: impl Fn(&[u8]) -> anyhow::Result<T>,
+) -> anyhow::Result<Vec<T>> {
+    let input = input.strip_suffix(b"\n").unwrap_or(input);
+    if input.is_empty() {
+        return Ok(Vec::new());
+    }
     input
-        .chunks(11)
-        .map(|chunk| id(unsafe { chunk.get_unchecked(..10) }))
+        .split(|&c| c == b'\n')
+        .enumerate()
+        .map(|(line_idx, line)| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            parse_line(line)
+                .with_context(|| format!("invalid boarding pass on line {}", line_idx + 1))
+        })
+        .collect()
+}
+
+/// The number of binary partitioning steps for rows (`F`/`B`) and columns (`L`/`R`).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PlaneLayout {
+    row_bits: u32,
+    column_bits: u32,
 }
 
-fn id(seat: &[u8]) -> u16 {
-    unsafe {
-        debug_assert!(seat.len() == 10);
-        if seat.len() != 10 {
-            unreachable_unchecked()
+/// The puzzle's plane: 128 rows of 8 seats.
+const DEFAULT_LAYOUT: PlaneLayout = PlaneLayout::new(7, 3);
+
+impl PlaneLayout {
+    pub const fn new(row_bits: u32, column_bits: u32) -> Self {
+        assert!(row_bits + column_bits <= 31, "plane layout too large");
+        PlaneLayout {
+            row_bits,
+            column_bits,
+        }
+    }
+
+    pub fn row_bits(self) -> u32 {
+        self.row_bits
+    }
+
+    pub fn column_bits(self) -> u32 {
+        self.column_bits
+    }
+
+    pub const fn rows(self) -> u32 {
+        1 << self.row_bits
+    }
+
+    pub const fn columns(self) -> u32 {
+        1 << self.column_bits
+    }
+
+    pub fn pass_length(self) -> usize {
+        (self.row_bits + self.column_bits) as usize
+    }
+
+    pub fn seats(self) -> u32 {
+        1 << (self.row_bits + self.column_bits)
+    }
+
+    fn validate(self, line: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            line.len() == self.pass_length(),
+            "expected {} characters, found {}",
+            self.pass_length(),
+            line.len()
+        );
+        for (idx, &c) in line.iter().enumerate() {
+            let expected = if idx < self.row_bits as usize {
+                b"FB"
+            } else {
+                b"LR"
+            };
+            anyhow::ensure!(
+                expected.contains(&c),
+                "invalid character {:?} in column {}, expected {:?} or {:?}",
+                c as char,
+                idx + 1,
+                expected[0] as char,
+                expected[1] as char
+            );
+        }
+        Ok(())
+    }
+
+    pub fn decode(self, pass: &[u8]) -> u32 {
+        pass.iter()
+            .fold(0, |acc, &c| (acc << 1) | (c == b'B' || c == b'R') as u32)
+    }
+
+    /// The id of the seat in `row` and `col`, or `None` if the seat is not on the plane.
+    pub fn seat_id(self, row: u32, col: u32) -> Option<u32> {
+        if row < self.rows() && col < self.columns() {
+            Some(row << self.column_bits | col)
+        } else {
+            None
+        }
+    }
+
+    /// The row and column of a seat id, or `None` if the id is not on the plane.
+    pub fn seat(self, id: u32) -> Option<(u32, u32)> {
+        if id < self.seats() {
+            Some((id >> self.column_bits, id & (self.columns() - 1)))
+        } else {
+            None
         }
-        let translate = |idx, letter| ((*seat.get_unchecked(idx) == letter) as u16) << (9 - idx);
-        (0..=6).fold(0u16, |acc, idx| acc | translate(idx, b'B'))
-            | (7..=9).fold(0u16, |acc, idx| acc | translate(idx, b'R'))
     }
+
+    /// The boarding pass for the seat in `row` and `col`, or `None` if the seat is not on the
+    /// plane.
+    pub fn encode_seat(self, row: u32, col: u32) -> Option<Vec<u8>> {
+        self.encode(self.seat_id(row, col)?)
+    }
+
+    /// The boarding pass for a seat id, or `None` if the id is not on the plane.
+    pub fn encode(self, id: u32) -> Option<Vec<u8>> {
+        if id >= self.seats() {
+            return None;
+        }
+        let pass = (0..self.pass_length())
+            .map(|idx| {
+                let bit = (id >> (self.pass_length() - 1 - idx)) & 1 == 1;
+                match (idx < self.row_bits as usize, bit) {
+                    (true, false) => b'F',
+                    (true, true) => b'B',
+                    (false, false) => b'L',
+                    (false, true) => b'R',
+                }
+            })
+            .collect();
+        Some(pass)
+    }
+
+    /// Draws the plane with one line per row, `#` for occupied seats and `.` for free ones. Fails
+    /// on seat ids that are not on the plane.
+    pub fn render_seat_map(
+        self,
+        occupied: impl IntoIterator<Item = u32>,
+    ) -> anyhow::Result<String> {
+        let mut seats = vec![false; self.seats() as usize];
+        for id in occupied {
+            *seats
+                .get_mut(id as usize)
+                .with_context(|| format!("seat id {} is not on the plane", id))? = true;
+        }
+        Ok(seats
+            .chunks(self.columns() as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|&taken| if taken { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+impl Default for PlaneLayout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT
+    }
+}
+
+/// Free seats between the first and last occupied seat, and the free seats before and after.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SeatGaps {
+    pub missing: Vec<u32>,
+    pub front: Range<u32>,
+    pub back: Range<u32>,
+}
+
+impl SeatGaps {
+    pub fn missing_front(&self) -> bool {
+        !self.front.is_empty()
+    }
+
+    pub fn missing_back(&self) -> bool {
+        !self.back.is_empty()
+    }
+}
+
+/// Fails if any occupied seat id is not on the plane.
+pub fn find_gaps(occupied: &[u32], layout: PlaneLayout) -> anyhow::Result<SeatGaps> {
+    let mut taken = FixedBitSet::with_capacity(layout.seats() as usize);
+    for &id in occupied {
+        anyhow::ensure!(
+            id < layout.seats(),
+            "seat id {} is not on a plane with {} seats",
+            id,
+            layout.seats()
+        );
+        taken.insert(id as usize);
+    }
+    Ok(match (taken.ones().next(), taken.ones().last()) {
+        (Some(min), Some(max)) => SeatGaps {
+            missing: (min..=max)
+                .filter(|&id| !taken[id])
+                .map(|id| id as u32)
+                .collect(),
+            front: 0..min as u32,
+            back: max as u32 + 1..layout.seats(),
+        },
+        _ => SeatGaps {
+            missing: Vec::new(),
+            front: 0..layout.seats(),
+            back: layout.seats()..layout.seats(),
+        },
+    })
+}
+
+pub fn id(seat: &[u8; 10]) -> u16 {
+    let translate = |idx: usize, letter| ((seat[idx] == letter) as u16) << (9 - idx);
+    (0..=6).fold(0u16, |acc, idx| acc | translate(idx, b'B'))
+        | (7..=9).fold(0u16, |acc, idx| acc | translate(idx, b'R'))
+}
+
+/// Same as [`id`] for valid boarding passes. Bit 2 of each letter is clear exactly for `B` and
+/// `R`; the multiplication gathers the flipped bits of the first eight letters into the top byte.
+fn id_fast(seat: &[u8; 10]) -> u16 {
+    const LOW_BITS: u64 = 0x0101_0101_0101_0101;
+    const GATHER: u64 = 0x0102_0408_1020_4080;
+    let head = u64::from_be_bytes(seat[..8].try_into().unwrap());
+    let head = ((!head >> 2) & LOW_BITS).wrapping_mul(GATHER) >> 56;
+    let tail = (!u16::from_be_bytes([seat[8], seat[9]]) >> 2) & 0x0101;
+    ((head as u16) << 2) | ((tail >> 7) & 0b10) | (tail & 1)
+}
+
+/// Whether the first seven letters are `F` or `B` and the last three `L` or `R`, checked the same
+/// way [`id_fast`] reads them: bit 2 picks the letter each byte has to be, and the pass is valid
+/// if it equals the pass built from those letters.
+fn is_valid_fast(seat: &[u8; 10]) -> bool {
+    const LOW_BITS: u64 = 0x0101_0101_0101_0101;
+    const HEAD_F: u64 = u64::from_be_bytes(*b"FFFFFFFL");
+    const HEAD_DIFF: u64 = u64::from_be_bytes([4, 4, 4, 4, 4, 4, 4, b'L' ^ b'R']);
+    const TAIL_L: u16 = u16::from_be_bytes(*b"LL");
+    const TAIL_DIFF: u16 = u16::from_be_bytes([b'L' ^ b'R'; 2]);
+    let head = u64::from_be_bytes(seat[..8].try_into().unwrap());
+    let head_mask = ((!head >> 2) & LOW_BITS) * 0xff;
+    let tail = u16::from_be_bytes([seat[8], seat[9]]);
+    let tail_mask = ((!tail >> 2) & 0x0101) * 0xff;
+    head == HEAD_F ^ (head_mask & HEAD_DIFF) && tail == TAIL_L ^ (tail_mask & TAIL_DIFF)
+}
+
+pub const ROWS: u16 = DEFAULT_LAYOUT.rows() as u16;
+pub const COLUMNS: u16 = DEFAULT_LAYOUT.columns() as u16;
+
+/// A seat on the plane of the puzzle, see [`PlaneLayout`] for other planes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Seat {
+    row: u8,
+    col: u8,
+}
+
+impl Seat {
+    /// `None` if the seat is not on the plane.
+    pub fn new(row: u8, col: u8) -> Option<Self> {
+        DEFAULT_LAYOUT
+            .seat_id(row.into(), col.into())
+            .map(|_| Seat { row, col })
+    }
+
+    pub fn from_id(id: u16) -> Option<Self> {
+        let (row, col) = DEFAULT_LAYOUT.seat(id.into())?;
+        Some(Seat {
+            row: row as u8,
+            col: col as u8,
+        })
+    }
+
+    pub fn row(self) -> u8 {
+        self.row
+    }
+
+    pub fn col(self) -> u8 {
+        self.col
+    }
+
+    pub fn id(self) -> u16 {
+        DEFAULT_LAYOUT
+            .seat_id(self.row.into(), self.col.into())
+            .unwrap() as u16
+    }
+
+    pub fn boarding_pass(self) -> [u8; 10] {
+        DEFAULT_LAYOUT
+            .encode(self.id().into())
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+}
+
+/// The boarding pass of a seat, or `None` if the seat is not on the plane.
+pub fn encode(row: u8, col: u8) -> Option<[u8; 10]> {
+    Seat::new(row, col).map(Seat::boarding_pass)
+}
+
+pub fn encode_id(id: u16) -> Option<[u8; 10]> {
+    Seat::from_id(id).map(Seat::boarding_pass)
+}
+
+/// [`PlaneLayout::render_seat_map`] for the plane of the puzzle.
+pub fn render_seat_map(occupied: impl IntoIterator<Item = u16>) -> anyhow::Result<String> {
+    DEFAULT_LAYOUT.render_seat_map(occupied.into_iter().map(u32::from))
 }
 
 fn range_sum(min: u32, max: u32) -> u32 {
@@ -49,6 +381,7 @@ fn range_sum(min: u32, max: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::xorshift::XorShift;
 
     #[test]
     fn id_test() {
@@ -56,4 +389,175 @@ mod tests {
         assert_eq!(id(b"FFFBBBFRRR"), 119);
         assert_eq!(id(b"BBFFBBFRLL"), 820);
     }
+
+    #[test]
+    fn id_fast_test() {
+        for seat_id in 0..ROWS * COLUMNS {
+            let pass = encode_id(seat_id).unwrap();
+            assert_eq!(id_fast(&pass), seat_id);
+            assert_eq!(id_fast(&pass), id(&pass));
+            assert!(is_valid_fast(&pass));
+        }
+        for &valid_pass in &[b"BFFFBBFRRR", b"FFFBBBFRRR", b"BBFFBBFRLL"] {
+            for idx in 0..valid_pass.len() {
+                for c in 0..=u8::MAX {
+                    let mut pass = *valid_pass;
+                    pass[idx] = c;
+                    let valid = PlaneLayout::default().validate(&pass).is_ok();
+                    assert_eq!(is_valid_fast(&pass), valid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_input_test() {
+        assert_eq!(
+            parse_input(b"BFFFBBFRRR\r\nFFFBBBFRRR\r\nBBFFBBFRLL\r\n").unwrap(),
+            vec![567, 119, 820]
+        );
+        assert_eq!(
+            parse_input(b"BFFFBBFRRR\nBBFFBBFRLL").unwrap(),
+            vec![567, 820]
+        );
+        assert!(part_1(b"").is_err());
+        assert!(part_2(b"\n").is_err());
+
+        let error = parse_input(b"BFFFBBFRRR\nBFFFBBFRR\n").unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "invalid boarding pass on line 2: expected 10 characters, found 9"
+        );
+        let error = parse_input(b"BFFFBBFRRR\nBFFFBBFRXR").unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "invalid boarding pass on line 2: invalid character 'X' in column 9, expected 'L' or 'R'"
+        );
+    }
+
+    #[test]
+    fn parse_input_fuzz_test() {
+        const ALPHABET: &[u8] = b"FBLRX\r\n";
+        let mut rng = XorShift::new(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..10_000 {
+            let mut input = Vec::new();
+            for _ in 0..rng.below(4) {
+                match rng.below(4) {
+                    0 => input.extend((0..rng.below(12)).map(|_| *rng.pick(ALPHABET))),
+                    1 => {
+                        let mut pass = encode_id(rng.below(1024) as u16).unwrap();
+                        pass[rng.below(10)] = *rng.pick(ALPHABET);
+                        input.extend_from_slice(&pass);
+                    }
+                    _ => input.extend_from_slice(&encode_id(rng.below(1024) as u16).unwrap()),
+                }
+                input.extend_from_slice(if rng.below(2) == 0 { b"\n" } else { b"\r\n" });
+            }
+
+            let text = input.strip_suffix(b"\n").unwrap_or(&input);
+            let lines = text
+                .split(|&c| c == b'\n')
+                .filter(|_| !text.is_empty())
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                .collect::<Vec<_>>();
+            let valid = lines.iter().all(|line| {
+                line.len() == 10
+                    && line[..7].iter().all(|c| b"FB".contains(c))
+                    && line[7..].iter().all(|c| b"LR".contains(c))
+            });
+            let result = parse_input(&input);
+            assert_eq!(
+                result.is_ok(),
+                valid,
+                "{:?}",
+                String::from_utf8_lossy(&input)
+            );
+            if valid {
+                let expected = lines
+                    .iter()
+                    .map(|line| id((*line).try_into().unwrap()))
+                    .collect::<Vec<_>>();
+                assert_eq!(result.unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn find_gaps_test() {
+        let layout = PlaneLayout::default();
+        let gaps = find_gaps(&[12, 10, 15, 13], layout).unwrap();
+        assert_eq!(gaps.missing, vec![11, 14]);
+        assert_eq!(gaps.front, 0..10);
+        assert_eq!(gaps.back, 16..1024);
+        assert!(gaps.missing_front() && gaps.missing_back());
+
+        let layout = PlaneLayout::new(2, 1);
+        let gaps = find_gaps(&[0, 1, 2, 5, 7], layout).unwrap();
+        assert_eq!(gaps.missing, vec![3, 4, 6]);
+        assert!(!gaps.missing_front() && !gaps.missing_back());
+        assert!(find_gaps(&[], layout).unwrap().missing_front());
+        assert!(find_gaps(&[0, 8], layout).is_err());
+        assert!(find_gaps(&[5000], PlaneLayout::default()).is_err());
+    }
+
+    #[test]
+    fn plane_layout_test() {
+        let layout = PlaneLayout::new(4, 2);
+        assert_eq!(layout.encode(0b101101), Some(b"BFBBLR".to_vec()));
+        assert_eq!(layout.encode(64), None);
+        assert_eq!(
+            parse_input_with(b"BFBBLR\nFFFFRR\n", layout).unwrap(),
+            vec![0b101101, 0b000011]
+        );
+        assert!(parse_input_with(b"BFFFBBFRRR", layout).is_err());
+        for id in 0..layout.seats() {
+            assert_eq!(layout.decode(&layout.encode(id).unwrap()), id);
+            let (row, col) = layout.seat(id).unwrap();
+            assert_eq!(layout.seat_id(row, col), Some(id));
+        }
+        assert_eq!((layout.rows(), layout.columns()), (16, 4));
+        assert_eq!(layout.encode_seat(11, 1), Some(b"BFBBLR".to_vec()));
+        assert_eq!(layout.encode_seat(16, 0), None);
+        assert_eq!(layout.seat_id(0, 4), None);
+        assert_eq!(layout.seat(64), None);
+        let default = PlaneLayout::default();
+        assert_eq!(
+            default.encode(567),
+            encode_id(567).map(|pass| pass.to_vec())
+        );
+    }
+
+    #[test]
+    fn encode_test() {
+        assert_eq!(&encode(70, 7).unwrap(), b"BFFFBBFRRR");
+        assert_eq!(&encode_id(820).unwrap(), b"BBFFBBFRLL");
+        assert_eq!(Seat::from_id(567), Seat::new(70, 7));
+        assert_eq!(encode(200, 0), None);
+        assert_eq!(encode(0, 8), None);
+        assert_eq!(encode_id(1024), None);
+        for seat_id in 0..ROWS * COLUMNS {
+            let seat = Seat::from_id(seat_id).unwrap();
+            assert_eq!(seat.id(), seat_id);
+            assert_eq!(id(&seat.boarding_pass()), seat_id);
+        }
+    }
+
+    #[test]
+    fn render_seat_map_test() {
+        let map = render_seat_map(vec![0, 9, 1023]).unwrap();
+        let rows = map.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), ROWS as usize);
+        assert_eq!(rows[0], "#.......");
+        assert_eq!(rows[1], ".#......");
+        assert_eq!(rows[127], ".......#");
+        assert!(render_seat_map(vec![0, 1024]).is_err());
+
+        let layout = PlaneLayout::new(2, 1);
+        assert_eq!(
+            layout.render_seat_map(vec![1, 6]).unwrap(),
+            ".#\n..\n..\n#."
+        );
+        assert!(layout.render_seat_map(vec![8]).is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use anyhow::Context;
 use aoc_runner_derive::aoc;
 use itertools::Itertools;
 use std::collections::HashSet;
@@ -11,8 +12,7 @@ pub fn part_1(input: &str) -> usize {
                 .chars()
                 .filter(|&c| c != '\n')
                 .collect::<HashSet<_>>()
-                .iter()
-                .count()
+                .len()
         })
         .sum()
 }
@@ -27,12 +27,200 @@ pub fn part_2(input: &str) -> usize {
                 .map(|member| member.chars().collect::<HashSet<_>>())
                 .fold1(|a, b| a.intersection(&b).cloned().collect())
                 .unwrap()
-                .iter()
-                .count()
+                .len()
         })
         .sum()
 }
 
+#[aoc(day6, part1, bitset)]
+pub fn part_1_bitset(input: &[u8]) -> u32 {
+    let mut total = 0;
+    let mut group = 0u32;
+    let mut line_start = true;
+
+    for &c in input {
+        match c {
+            b'\n' if line_start => {
+                total += group.count_ones();
+                group = 0;
+            }
+            b'\n' => line_start = true,
+            b'\r' => {}
+            _ => {
+                group |= question_bit(c);
+                line_start = false;
+            }
+        }
+    }
+    total + group.count_ones()
+}
+
+#[aoc(day6, part2, bitset)]
+pub fn part_2_bitset(input: &[u8]) -> u32 {
+    let mut total = 0;
+    let mut group = u32::MAX;
+    let mut member = 0u32;
+    let mut line_start = true;
+
+    for &c in input {
+        match c {
+            b'\n' if line_start => {
+                if group != u32::MAX {
+                    total += group.count_ones();
+                }
+                group = u32::MAX;
+            }
+            b'\n' => {
+                group &= member;
+                member = 0;
+                line_start = true;
+            }
+            b'\r' => {}
+            _ => {
+                member |= question_bit(c);
+                line_start = false;
+            }
+        }
+    }
+    if !line_start {
+        group &= member;
+    }
+    if group != u32::MAX {
+        total += group.count_ones();
+    }
+    total
+}
+
+/// Only meaningful for `a..=z`; other bytes map to arbitrary bits instead of panicking.
+fn question_bit(c: u8) -> u32 {
+    1 << (c.wrapping_sub(b'a') & 31)
+}
+
+/// The questions answered "yes", as a bitset over `a..=z`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Answers(u32);
+
+impl Answers {
+    pub const ALL: Answers = Answers((1 << 26) - 1);
+
+    pub fn parse(member: &str) -> anyhow::Result<Self> {
+        member.bytes().try_fold(Answers::default(), |answers, c| {
+            anyhow::ensure!(c.is_ascii_lowercase(), "invalid question {:?}", c as char);
+            Ok(Answers(answers.0 | 1 << (c - b'a')))
+        })
+    }
+
+    pub fn contains(self, question: char) -> bool {
+        question.is_ascii_lowercase() && self.0 & 1 << (question as u8 - b'a') != 0
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn union(self, other: Answers) -> Answers {
+        Answers(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Answers) -> Answers {
+        Answers(self.0 & other.0)
+    }
+
+    pub fn symmetric_difference(self, other: Answers) -> Answers {
+        Answers(self.0 ^ other.0)
+    }
+
+    pub fn questions(self) -> impl Iterator<Item = char> {
+        (b'a'..=b'z')
+            .map(char::from)
+            .filter(move |&question| self.contains(question))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct GroupAnswers {
+    members: Vec<Answers>,
+}
+
+impl GroupAnswers {
+    pub fn new(members: Vec<Answers>) -> Self {
+        GroupAnswers { members }
+    }
+
+    pub fn parse(group: &str) -> anyhow::Result<Self> {
+        group
+            .lines()
+            .map(Answers::parse)
+            .collect::<anyhow::Result<_>>()
+            .map(GroupAnswers::new)
+    }
+
+    pub fn members(&self) -> &[Answers] {
+        &self.members
+    }
+
+    /// Questions answered by anyone.
+    pub fn union(&self) -> Answers {
+        self.members
+            .iter()
+            .fold(Answers::default(), |acc, &member| acc.union(member))
+    }
+
+    /// Questions answered by everyone. Empty for a group without members.
+    pub fn intersection(&self) -> Answers {
+        if self.members.is_empty() {
+            return Answers::default();
+        }
+        self.members
+            .iter()
+            .fold(Answers::ALL, |acc, &member| acc.intersection(member))
+    }
+
+    /// Questions answered by an odd number of members.
+    pub fn symmetric_difference(&self) -> Answers {
+        self.members
+            .iter()
+            .fold(Answers::default(), |acc, &member| {
+                acc.symmetric_difference(member)
+            })
+    }
+
+    /// Questions answered by at least `k` members.
+    pub fn at_least(&self, k: usize) -> Answers {
+        let histogram = self.histogram();
+        Answers(
+            (0..26)
+                .filter(|&idx| histogram[idx] >= k)
+                .fold(0, |acc, idx| acc | 1 << idx),
+        )
+    }
+
+    /// How many members answered each question, indexed from `a`.
+    pub fn histogram(&self) -> [usize; 26] {
+        let mut histogram = [0; 26];
+        for member in &self.members {
+            for (idx, count) in histogram.iter_mut().enumerate() {
+                *count += (member.0 >> idx & 1) as usize;
+            }
+        }
+        histogram
+    }
+}
+
+pub fn parse_groups(input: &str) -> anyhow::Result<Vec<GroupAnswers>> {
+    input
+        .split("\n\n")
+        .enumerate()
+        .map(|(idx, group)| {
+            GroupAnswers::parse(group).with_context(|| format!("invalid group {}", idx + 1))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,4 +248,35 @@ b";
     fn part_2_test() {
         assert_eq!(part_2(LIST), 6);
     }
+
+    #[test]
+    fn bitset_test() {
+        assert_eq!(part_1_bitset(LIST.as_bytes()), 11);
+        assert_eq!(part_2_bitset(LIST.as_bytes()), 6);
+        let crlf = LIST.replace('\n', "\r\n") + "\r\n";
+        assert_eq!(part_1_bitset(crlf.as_bytes()), 11);
+        assert_eq!(part_2_bitset(crlf.as_bytes()), 6);
+    }
+
+    #[test]
+    fn group_answers_test() {
+        let groups = parse_groups(LIST).unwrap();
+        assert_eq!(groups.iter().map(|g| g.union().len()).sum::<usize>(), 11);
+        assert_eq!(
+            groups.iter().map(|g| g.intersection().len()).sum::<usize>(),
+            6
+        );
+
+        let group = GroupAnswers::parse("abc\nabd\nbe").unwrap();
+        assert_eq!(group.histogram()[..5], [2, 3, 1, 1, 1]);
+        assert_eq!(group.at_least(2).questions().collect::<String>(), "ab");
+        assert_eq!(group.at_least(1), group.union());
+        assert_eq!(group.at_least(3), group.intersection());
+        assert_eq!(
+            group.symmetric_difference().questions().collect::<String>(),
+            "bcde"
+        );
+        assert!(GroupAnswers::default().intersection().is_empty());
+        assert!(parse_groups("ab\n\nA").is_err());
+    }
 }
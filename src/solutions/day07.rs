@@ -1,101 +1,381 @@
+use anyhow::Context;
 use aoc_runner_derive::aoc;
 use nom::{
     branch::alt,
     bytes::complete::tag,
     character::complete::{alpha1, digit1},
-    combinator::{map, opt, recognize, value},
+    combinator::{eof, map_res, not, recognize, verify},
+    error::ErrorKind,
     multi::separated_list1,
-    sequence::{separated_pair, terminated, tuple},
+    sequence::terminated,
     IResult,
 };
 use petgraph::{
+    algo::toposort,
     graph::{DiGraph, NodeIndex},
-    visit::{Dfs, Walker},
+    visit::{Dfs, EdgeRef, Reversed, Walker},
 };
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
+
+const SHINY_GOLD: &str = "shiny gold";
 
 #[aoc(day7, part1)]
-pub fn part_1(input: &str) -> usize {
-    let (graph, node_id) = create_graph(input);
-
-    Dfs::new(&graph, node_id["shiny gold"])
-        .iter(&graph)
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .count()
-        - 1
+pub fn part_1(input: &str) -> anyhow::Result<usize> {
+    Ok(BagRules::new(input)?.ancestors(SHINY_GOLD)?.len())
 }
 
 #[aoc(day7, part2)]
-pub fn part_2(input: &str) -> usize {
-    let map = parse_input(input);
-    let shiny_gold_name = "shiny gold".to_owned();
-    let mut stack = vec![(&shiny_gold_name, 1usize)];
-    let mut total = 0usize;
+pub fn part_2(input: &str) -> anyhow::Result<usize> {
+    BagRules::new(input)?.contained_count(SHINY_GOLD)
+}
 
-    while let Some((bag_name, count)) = stack.pop() {
-        total += count;
-        stack.extend(map[bag_name].iter().map(|(b, c)| (b, *c * count)));
-    }
+/// Bag rules as a graph with an edge from every container to each bag it holds, weighted by the
+/// number of bags held. Nodes are numbered in order of their names.
+#[derive(Clone, Debug)]
+pub struct BagRules {
+    graph: DiGraph<String, usize, u32>,
+    node_id: HashMap<String, NodeIndex<u32>>,
+    topological_order: Vec<NodeIndex<u32>>,
+    /// The number of bags inside every bag, or `None` where it does not fit into a `usize`.
+    counts: Vec<Option<usize>>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct DotOptions<'a> {
+    pub focus: Option<&'a str>,
+    pub reachable_only: bool,
+}
 
-    total - 1
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BagTree<'a> {
+    pub name: &'a str,
+    pub count: usize,
+    pub children: Vec<BagTree<'a>>,
 }
 
-fn create_graph(input: &str) -> (DiGraph<String, usize, u32>, HashMap<String, NodeIndex<u32>>) {
-    let map = parse_input(input);
+impl BagRules {
+    pub fn new(input: &str) -> anyhow::Result<Self> {
+        let map = parse_input(input)?;
 
-    let mut graph = DiGraph::<String, usize, _>::new();
+        let mut graph = DiGraph::<String, usize, _>::new();
 
-    let node_id = map
-        .iter()
-        .flat_map(|(container, children)| std::iter::once(container).chain(children.keys()))
-        .collect::<HashSet<_>>()
-        .into_iter()
-        .map(|bag_name| (bag_name.clone(), graph.add_node(bag_name.clone())))
-        .collect::<HashMap<_, _>>();
+        let node_id = map
+            .iter()
+            .flat_map(|(container, children)| std::iter::once(container).chain(children.keys()))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|bag_name| (bag_name.clone(), graph.add_node(bag_name.clone())))
+            .collect::<HashMap<_, _>>();
 
-    graph.extend_with_edges(map.into_iter().flat_map(|(container, children)| {
-        children.into_iter().map({
-            let node_id = &node_id;
-            move |(child, count)| (node_id[&child], node_id[&container], count)
+        graph.extend_with_edges(map.into_iter().flat_map(|(container, children)| {
+            children.into_iter().map({
+                let node_id = &node_id;
+                move |(child, count)| (node_id[&container], node_id[&child], count)
+            })
+        }));
+
+        let topological_order = toposort(&graph, None).map_err(|cycle| {
+            anyhow::anyhow!(
+                "bag rules are recursive: {:?} contains itself",
+                graph[cycle.node_id()]
+            )
+        })?;
+        let counts = checked_counts(&graph, &topological_order);
+
+        Ok(BagRules {
+            graph,
+            node_id,
+            topological_order,
+            counts,
         })
-    }));
-    (graph, node_id)
+    }
+
+    pub fn graph(&self) -> &DiGraph<String, usize, u32> {
+        &self.graph
+    }
+
+    fn node(&self, bag: &str) -> anyhow::Result<NodeIndex<u32>> {
+        self.node_id
+            .get(bag)
+            .copied()
+            .with_context(|| format!("unknown bag {:?}", bag))
+    }
+
+    /// All bags that eventually contain the given bag, sorted by name.
+    pub fn ancestors(&self, bag: &str) -> anyhow::Result<Vec<&str>> {
+        let reversed = Reversed(&self.graph);
+        let start = self.node(bag)?;
+        let mut ancestors = Dfs::new(reversed, start)
+            .iter(reversed)
+            .filter(|&node| node != start)
+            .map(|node| self.graph[node].as_str())
+            .collect::<Vec<_>>();
+        ancestors.sort_unstable();
+        Ok(ancestors)
+    }
+
+    /// The number of bags inside the given bag, not counting the bag itself.
+    pub fn contained_count(&self, bag: &str) -> anyhow::Result<usize> {
+        self.counts[self.node(bag)?.index()]
+            .with_context(|| format!("number of bags inside {:?} overflows", bag))
+    }
+
+    /// The number of bags inside every bag.
+    pub fn contained_counts(&self) -> anyhow::Result<HashMap<&str, usize>> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(idx, &count)| {
+                let bag = self.graph[NodeIndex::new(idx)].as_str();
+                count
+                    .map(|count| (bag, count))
+                    .with_context(|| format!("number of bags inside {:?} overflows", bag))
+            })
+            .collect()
+    }
+
+    pub fn containment_tree(&self, bag: &str) -> anyhow::Result<BagTree<'_>> {
+        fn build(rules: &BagRules, node: NodeIndex<u32>, count: usize) -> BagTree<'_> {
+            let mut children = rules
+                .graph
+                .edges(node)
+                .map(|edge| build(rules, edge.target(), *edge.weight()))
+                .collect::<Vec<_>>();
+            children.sort_unstable_by_key(|child| child.name);
+            BagTree {
+                name: &rules.graph[node],
+                count,
+                children,
+            }
+        }
+
+        Ok(build(self, self.node(bag)?, 1))
+    }
+
+    fn descendants(&self, start: NodeIndex<u32>) -> HashSet<NodeIndex<u32>> {
+        Dfs::new(&self.graph, start)
+            .iter(&self.graph)
+            .filter(|&node| node != start)
+            .collect()
+    }
+
+    /// Writes the rules as a Graphviz digraph with the counts as edge labels. With a focus bag, the
+    /// bag, its ancestors and its descendants are colored, and `reachable_only` limits the output
+    /// to the focus bag and the bags inside it.
+    pub fn to_dot(&self, options: &DotOptions) -> anyhow::Result<String> {
+        let mut ancestors = HashSet::new();
+        let mut descendants = HashSet::new();
+        let focus = options.focus.map(|bag| self.node(bag)).transpose()?;
+        if let Some(focus) = focus {
+            ancestors = self
+                .ancestors(&self.graph[focus])?
+                .into_iter()
+                .map(|bag| self.node_id[bag])
+                .collect();
+            descendants = self.descendants(focus);
+        } else {
+            anyhow::ensure!(
+                !options.reachable_only,
+                "reachable_only requires a focus bag"
+            );
+        }
+        let included =
+            |node| !options.reachable_only || Some(node) == focus || descendants.contains(&node);
+        let escape = |name: &str| name.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut output = String::from("digraph bags {\n");
+        for node in self.graph.node_indices().filter(|&node| included(node)) {
+            let color = if Some(node) == focus {
+                Some("gold")
+            } else if ancestors.contains(&node) {
+                Some("lightblue")
+            } else if descendants.contains(&node) {
+                Some("palegreen")
+            } else {
+                None
+            };
+            output.push_str(&format!(
+                "    {} [label=\"{}\"",
+                node.index(),
+                escape(&self.graph[node])
+            ));
+            if let Some(color) = color {
+                output.push_str(&format!(", style=filled, fillcolor={}", color));
+            }
+            output.push_str("];\n");
+        }
+        let mut edges = self
+            .graph
+            .edge_references()
+            .filter(|edge| included(edge.source()) && included(edge.target()))
+            .collect::<Vec<_>>();
+        edges.sort_unstable_by_key(|edge| (edge.source(), edge.target()));
+        for edge in edges {
+            output.push_str(&format!(
+                "    {} -> {} [label=\"{}\"];\n",
+                edge.source().index(),
+                edge.target().index(),
+                edge.weight()
+            ));
+        }
+        output.push_str("}\n");
+        Ok(output)
+    }
+
+    /// Every bag, with each container before the bags it contains.
+    pub fn topological_order(&self) -> Vec<&str> {
+        self.topological_order
+            .iter()
+            .map(|&node| self.graph[node].as_str())
+            .collect()
+    }
+}
+
+/// Evaluates every bag once, contents first, so each count is the sum over the edges of
+/// `weight * (1 + count of the child)`. `None` marks counts that do not fit into a `usize`.
+fn checked_counts(
+    graph: &DiGraph<String, usize, u32>,
+    topological_order: &[NodeIndex<u32>],
+) -> Vec<Option<usize>> {
+    let mut counts = vec![Some(0usize); graph.node_count()];
+    for &node in topological_order.iter().rev() {
+        counts[node.index()] = graph.edges(node).try_fold(0usize, |total, edge| {
+            counts[edge.target().index()]?
+                .checked_add(1)?
+                .checked_mul(*edge.weight())?
+                .checked_add(total)
+        });
+    }
+    counts
 }
 
-fn parse_input(input: &str) -> HashMap<String, HashMap<String, usize>> {
-    input
-        .split('\n')
-        .map(|line| parse_line(line).unwrap().1)
-        .collect::<HashMap<_, _>>()
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum RuleError {
+    Syntax {
+        line: usize,
+        column: usize,
+        expected: &'static str,
+    },
+    Duplicate {
+        line: usize,
+        first_line: usize,
+        bag: String,
+    },
 }
 
-fn parse_line(i: &str) -> IResult<&str, (String, HashMap<String, usize>)> {
-    let mut bag_name_parser = recognize(tuple((alpha1, tag(" "), alpha1)));
-    let (i, bag_name) = bag_name_parser(i)?;
-    let (i, _) = tag(" bags contain ")(i)?;
-    let (i, children) = alt((
-        value(HashMap::new(), tag("no other bags")),
-        map(
-            separated_list1(
-                tag(", "),
-                terminated(
-                    map(
-                        separated_pair(
-                            map(digit1, |s: &str| s.parse::<usize>().unwrap()),
-                            tag(" "),
-                            bag_name_parser,
-                        ),
-                        |(c, n)| (n.to_owned(), c),
-                    ),
-                    tuple((tag(" bag"), opt(tag("s")))),
-                ),
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Syntax {
+                line,
+                column,
+                expected,
+            } => write!(f, "line {}, column {}: expected {}", line, column, expected),
+            RuleError::Duplicate {
+                line,
+                first_line,
+                bag,
+            } => write!(
+                f,
+                "line {}: rule for {:?} is already defined on line {}",
+                line, bag, first_line
             ),
-            |list| list.into_iter().collect(),
-        ),
-    ))(i)?;
-    let (i, _) = tag(".")(i)?;
-    Ok((i, (bag_name.to_owned(), children)))
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Parses one rule per line. Blank lines are skipped and line endings may be `\n` or `\r\n`.
+fn parse_input(input: &str) -> Result<HashMap<String, HashMap<String, usize>>, RuleError> {
+    let mut rules = HashMap::new();
+    let mut first_lines = HashMap::new();
+
+    for (line_idx, line) in input.lines().enumerate() {
+        let line_number = line_idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (bag, children) = parse_line(line).map_err(|(rest, expected)| RuleError::Syntax {
+            line: line_number,
+            column: line[..line.len() - rest.len()].chars().count() + 1,
+            expected,
+        })?;
+        if let Some(&first_line) = first_lines.get(bag) {
+            return Err(RuleError::Duplicate {
+                line: line_number,
+                first_line,
+                bag: bag.to_owned(),
+            });
+        }
+        first_lines.insert(bag, line_number);
+
+        let mut contents = HashMap::new();
+        for (child, count) in children {
+            *contents.entry(child.to_owned()).or_default() += count;
+        }
+        rules.insert(bag.to_owned(), contents);
+    }
+    Ok(rules)
+}
+
+type Rule<'a> = (&'a str, Vec<(&'a str, usize)>);
+
+/// On failure, returns the unparsed rest of the line and a description of what was expected
+/// there.
+fn parse_line(i: &str) -> Result<Rule<'_>, (&str, &'static str)> {
+    fn expect<'a, O>(
+        mut parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+        expected: &'static str,
+    ) -> impl FnMut(&'a str) -> Result<(&'a str, O), (&'a str, &'static str)> {
+        move |i| parser(i).map_err(|_| (i, expected))
+    }
+
+    let (i, bag) = expect(bag_name, "a bag name")(i)?;
+    let (mut i, _) = expect(tag(" bags contain "), "\" bags contain \"")(i)?;
+    let mut children = Vec::new();
+
+    if let Ok((rest, _)) = tag::<_, _, (&str, ErrorKind)>("no other bags")(i) {
+        i = expect(tag("."), "\".\"")(rest)?.0;
+    } else {
+        loop {
+            let expected_count = if children.is_empty() {
+                "a number or \"no other bags\""
+            } else {
+                "a number"
+            };
+            let (rest, count) = expect(map_res(digit1, str::parse::<usize>), expected_count)(i)?;
+            let (rest, _) = expect(tag(" "), "\" \"")(rest)?;
+            let (rest, child) = expect(bag_name, "a bag name")(rest)?;
+            let (rest, _) = expect(tag(" "), "\" \"")(rest)?;
+            let (rest, _) = expect(
+                verify(alpha1, |word: &str| word == "bag" || word == "bags"),
+                "\"bag\" or \"bags\"",
+            )(rest)?;
+            children.push((child, count));
+            let (rest, separator) = expect(alt((tag(", "), tag("."))), "\", \" or \".\"")(rest)?;
+            i = rest;
+            if separator == "." {
+                break;
+            }
+        }
+    }
+
+    if !i.is_empty() {
+        return Err((i, "end of line"));
+    }
+    Ok((bag, children))
+}
+
+/// One or more words, up to but not including the word right before `,`, `.`, ` contain` or the
+/// end of the line. That word is the `bag`/`bags` unit, so a misspelled unit is reported instead
+/// of becoming part of the name, while colours like `baggy green` still parse.
+fn bag_name(i: &str) -> IResult<&str, &str> {
+    let unit_end = alt((tag(","), tag("."), tag(" contain"), eof));
+    recognize(separated_list1(tag(" "), terminated(alpha1, not(unit_end))))(i)
 }
 
 #[cfg(test)]
@@ -113,12 +393,12 @@ dotted black bags contain no other bags.";
 
     #[test]
     fn part_1_test() {
-        assert_eq!(part_1(EXAMPLE), 4);
+        assert_eq!(part_1(EXAMPLE).unwrap(), 4);
     }
 
     #[test]
     fn part_2_test() {
-        assert_eq!(part_2(EXAMPLE), 32);
+        assert_eq!(part_2(EXAMPLE).unwrap(), 32);
         assert_eq!(
             part_2(
                 "shiny gold bags contain 2 dark red bags.
@@ -128,8 +408,221 @@ dark yellow bags contain 2 dark green bags.
 dark green bags contain 2 dark blue bags.
 dark blue bags contain 2 dark violet bags.
 dark violet bags contain no other bags."
-            ),
+            )
+            .unwrap(),
             126
         );
     }
+
+    #[test]
+    fn bag_rules_test() {
+        let rules = BagRules::new(EXAMPLE).unwrap();
+        assert_eq!(
+            rules.ancestors("shiny gold").unwrap(),
+            vec!["bright white", "dark orange", "light red", "muted yellow"]
+        );
+        assert!(rules.ancestors("light red").unwrap().is_empty());
+        assert_eq!(rules.contained_count("dark olive").unwrap(), 7);
+        assert!(rules.contained_count("plaid magenta").is_err());
+
+        let tree = rules.containment_tree("dark olive").unwrap();
+        assert_eq!(tree.name, "dark olive");
+        assert_eq!(
+            tree.children
+                .iter()
+                .map(|child| (child.name, child.count, child.children.len()))
+                .collect::<Vec<_>>(),
+            vec![("dotted black", 4, 0), ("faded blue", 3, 0)]
+        );
+
+        let order = rules.topological_order();
+        assert_eq!(order.len(), 9);
+        let position = |bag| order.iter().position(|&b| b == bag).unwrap();
+        assert!(position("light red") < position("shiny gold"));
+        assert!(position("shiny gold") < position("dark olive"));
+        assert!(position("dark olive") < position("faded blue"));
+    }
+
+    #[test]
+    fn to_dot_test() {
+        let rules = BagRules::new(
+            "light red bags contain 1 shiny gold bag.
+shiny gold bags contain 2 dark red bags.
+dark red bags contain no other bags.
+pale blue bags contain no other bags.",
+        )
+        .unwrap();
+        let id = |bag: &str| rules.node_id[bag].index();
+
+        assert_eq!(
+            rules.to_dot(&DotOptions::default()).unwrap(),
+            concat!(
+                "digraph bags {\n",
+                "    0 [label=\"dark red\"];\n",
+                "    1 [label=\"light red\"];\n",
+                "    2 [label=\"pale blue\"];\n",
+                "    3 [label=\"shiny gold\"];\n",
+                "    1 -> 3 [label=\"1\"];\n",
+                "    3 -> 0 [label=\"2\"];\n",
+                "}\n",
+            )
+        );
+
+        let dot = rules
+            .to_dot(&DotOptions {
+                focus: Some("shiny gold"),
+                reachable_only: false,
+            })
+            .unwrap();
+        assert!(dot.contains(&format!(
+            "    {} [label=\"shiny gold\", style=filled, fillcolor=gold];",
+            id("shiny gold")
+        )));
+        assert!(dot.contains(&format!(
+            "    {} [label=\"light red\", style=filled, fillcolor=lightblue];",
+            id("light red")
+        )));
+        assert!(dot.contains(&format!(
+            "    {} [label=\"dark red\", style=filled, fillcolor=palegreen];",
+            id("dark red")
+        )));
+        assert!(dot.contains(&format!("    {} [label=\"pale blue\"];", id("pale blue"))));
+
+        let dot = rules
+            .to_dot(&DotOptions {
+                focus: Some("shiny gold"),
+                reachable_only: true,
+            })
+            .unwrap();
+        assert!(!dot.contains("light red") && !dot.contains("pale blue"));
+        assert_eq!(dot.matches(" -> ").count(), 1);
+        assert!(rules
+            .to_dot(&DotOptions {
+                focus: None,
+                reachable_only: true,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn contained_counts_test() {
+        let rules = BagRules::new(EXAMPLE).unwrap();
+        let counts = rules.contained_counts().unwrap();
+        assert_eq!(counts["shiny gold"], 32);
+        assert_eq!(counts["faded blue"], 0);
+        assert_eq!(counts["dark olive"], 7);
+
+        // Every level holds the next one twice over two paths, so expanding the tree takes
+        // about 2^63 steps while the memoized evaluation visits each bag once.
+        let level = |n: usize| {
+            n.to_string()
+                .bytes()
+                .map(|d| (d - b'0' + b'a') as char)
+                .collect::<String>()
+        };
+        let deep = (0..63)
+            .map(|n| {
+                format!(
+                    "level {0} bags contain 1 left {0} bag, 1 right {0} bag.
+left {0} bags contain 1 level {1} bag.
+right {0} bags contain 1 level {1} bag.",
+                    level(n),
+                    level(n + 1)
+                )
+            })
+            .chain(std::iter::once(format!(
+                "level {} bags contain no other bags.",
+                level(63)
+            )))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let rules = BagRules::new(&deep).unwrap();
+        assert_eq!(
+            rules
+                .contained_count(&format!("level {}", level(53)))
+                .unwrap(),
+            4 * ((1 << 10) - 1)
+        );
+        assert_eq!(
+            rules
+                .contained_count(&format!("level {}", level(1)))
+                .unwrap(),
+            4 * ((1usize << 62) - 1)
+        );
+        assert!(rules
+            .contained_count(&format!("level {}", level(0)))
+            .is_err());
+        assert!(rules.contained_counts().is_err());
+    }
+
+    #[test]
+    fn parse_input_test() {
+        let rules = parse_input(
+            "very shiny gold bags contain 1 dark red bag, 2 pale light blue bags.\r
+dark red bags contain no other bags.\r
+\r
+pale light blue bags contain 3 dark red bags.\r
+",
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules["very shiny gold"]["pale light blue"], 2);
+        assert_eq!(rules["pale light blue"]["dark red"], 3);
+        assert!(rules["dark red"].is_empty());
+
+        let rules = parse_input(
+            "baggy green bags contain 2 bagel brown bags.\nbagel brown bags contain no other bags.",
+        )
+        .unwrap();
+        assert_eq!(rules["baggy green"]["bagel brown"], 2);
+        assert!(rules["bagel brown"].is_empty());
+
+        let error = |input| parse_input(input).unwrap_err().to_string();
+        assert_eq!(
+            error("dark red bags contain no other bags.\nshiny gold bags contains 1 dark red bag."),
+            "line 2, column 11: expected \" bags contain \""
+        );
+        assert_eq!(
+            error("shiny gold bags contain 1 dark red bag, two dark blue bags."),
+            "line 1, column 41: expected a number"
+        );
+        assert_eq!(
+            error("shiny gold bags contain 1 dark red bagz."),
+            "line 1, column 36: expected \"bag\" or \"bags\""
+        );
+        assert_eq!(
+            error("shiny gold bags contain 1 dark red bagz, 2 pale blue bags."),
+            "line 1, column 36: expected \"bag\" or \"bags\""
+        );
+        assert_eq!(
+            error("shiny gold bagz contain 1 dark red bag."),
+            "line 1, column 11: expected \" bags contain \""
+        );
+        assert_eq!(
+            error("shiny gold bags contain 1 dark red bag"),
+            "line 1, column 39: expected \", \" or \".\""
+        );
+        assert_eq!(
+            error("shiny gold bags contain no other bags. x"),
+            "line 1, column 39: expected end of line"
+        );
+        assert_eq!(
+            parse_input("shiny gold bags contain no other bags.\n\nshiny gold bags contain 1 dark red bag.\n"),
+            Err(RuleError::Duplicate {
+                line: 3,
+                first_line: 1,
+                bag: "shiny gold".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn recursive_rules_test() {
+        let error = BagRules::new(
+            "shiny gold bags contain 1 dark red bag.
+dark red bags contain 2 shiny gold bags.",
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("bag rules are recursive"));
+    }
 }
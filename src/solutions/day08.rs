@@ -1,115 +1,942 @@
 use anyhow::Context;
 use aoc_runner_derive::{aoc, aoc_generator};
-use std::mem::replace;
+use fixedbitset::FixedBitSet;
+use petgraph::{
+    algo::tarjan_scc,
+    graph::{DiGraph, NodeIndex},
+    visit::{Dfs, Walker},
+    Direction,
+};
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom, fmt};
 
 type GeneratorOutput = Vec<Instruction>;
 type PartInput = [Instruction];
 
 #[aoc_generator(day8)]
 pub fn generator(input: &str) -> anyhow::Result<GeneratorOutput> {
-    input
-        .split('\n')
-        .map(|line| {
-            let op = match &line[..3] {
-                "nop" => Operation::Nop,
-                "jmp" => Operation::Jmp,
-                "acc" => Operation::Acc,
-                _ => anyhow::bail!("unknown opcode"),
-            };
-            let arg = line[4..].parse::<i64>().context("number parsing failed")?;
-            Ok(Instruction { op, arg })
-        })
-        .collect()
+    Ok(assemble(input, &OperationSet::default())?)
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Operation {
     Acc,
     Jmp,
     Nop,
+    /// An operation registered in an [`OperationSet`], by registration order.
+    Custom(usize),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Instruction {
-    op: Operation,
-    arg: i64,
+    pub op: Operation,
+    pub arg: i64,
 }
 
-#[aoc(day8, part1)]
-pub fn part_1(input: &PartInput) -> i64 {
-    if let RunResult::InfiniteLoop(result) = run_code(input) {
-        return result;
+impl Instruction {
+    pub fn new(op: Operation, arg: i64) -> Self {
+        Instruction { op, arg }
     }
-    unreachable!()
 }
 
-#[aoc(day8, part2)]
-pub fn part_2(input: &PartInput) -> i64 {
-    fn swap_op(op: &mut Operation) {
+/// The registers of the console.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct State {
+    pub pc: usize,
+    pub acc: i64,
+}
+
+/// Executes a custom instruction with its argument. Unlike the built-in operations, it has to
+/// advance `pc` itself.
+pub type CustomOperation = fn(&mut State, i64);
+
+/// The built-in operations and any custom ones, addressed by their mnemonics.
+#[derive(Clone, Debug, Default)]
+pub struct OperationSet {
+    custom: Vec<(String, CustomOperation)>,
+}
+
+impl OperationSet {
+    /// Fails if `mnemonic` already names a built-in or registered operation.
+    pub fn register(
+        &mut self,
+        mnemonic: &str,
+        operation: CustomOperation,
+    ) -> anyhow::Result<Operation> {
+        if self.lookup(mnemonic).is_some() {
+            anyhow::bail!("operation {:?} is already defined", mnemonic);
+        }
+        self.custom.push((mnemonic.to_owned(), operation));
+        Ok(Operation::Custom(self.custom.len() - 1))
+    }
+
+    pub fn lookup(&self, mnemonic: &str) -> Option<Operation> {
+        match mnemonic {
+            "acc" => Some(Operation::Acc),
+            "jmp" => Some(Operation::Jmp),
+            "nop" => Some(Operation::Nop),
+            _ => self
+                .custom
+                .iter()
+                .position(|(name, _)| name == mnemonic)
+                .map(Operation::Custom),
+        }
+    }
+
+    pub fn mnemonic(&self, op: Operation) -> Option<&str> {
         match op {
-            op @ Operation::Jmp => *op = Operation::Nop,
-            op @ Operation::Nop => *op = Operation::Jmp,
-            _ => unreachable!(),
+            Operation::Acc => Some("acc"),
+            Operation::Jmp => Some("jmp"),
+            Operation::Nop => Some("nop"),
+            Operation::Custom(idx) => self.custom.get(idx).map(|(name, _)| name.as_str()),
         }
     }
 
-    let mut code = input.to_vec();
-    for idx in 0..code.len() {
-        if code[idx].op != Operation::Acc {
-            swap_op(&mut code[idx].op);
-            match run_code(&code) {
-                RunResult::InfiniteLoop(_) | RunResult::Error => {
-                    swap_op(&mut code[idx].op);
+    fn execute(&self, instruction: Instruction, state: &mut State) -> Result<(), Stop> {
+        match instruction.op {
+            Operation::Acc => {
+                state.acc = state
+                    .acc
+                    .checked_add(instruction.arg)
+                    .ok_or(Stop::Overflow(state.pc))?;
+                state.pc += 1;
+            }
+            Operation::Jmp => {
+                state.pc = state.pc.wrapping_add(instruction.arg as usize);
+            }
+            Operation::Nop => {
+                state.pc += 1;
+            }
+            Operation::Custom(idx) => match self.custom.get(idx) {
+                Some((_, operation)) => operation(state, instruction.arg),
+                None => return Err(Stop::UnknownOperation(state.pc)),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Why the machine stopped. Every reason except [`Stop::StepLimit`] and [`Stop::Condition`] is
+/// reported before the instruction at `pc` executes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Stop {
+    /// `pc` points right behind the last instruction.
+    Terminated,
+    /// `pc` points anywhere else outside of the program.
+    OutOfBounds,
+    /// The instruction at `pc` has been executed before.
+    InfiniteLoop,
+    Breakpoint(usize),
+    StepLimit,
+    Condition,
+    UnknownOperation(usize),
+    /// Executing the instruction at `pc` would overflow the accumulator.
+    Overflow(usize),
+}
+
+/// An executed instruction, with the accumulator after executing it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub instruction: Instruction,
+    pub acc: i64,
+}
+
+/// A loop in the executed instructions, starting at `entry`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Cycle {
+    pub entry: usize,
+    pub instructions: Vec<usize>,
+}
+
+impl Cycle {
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+}
+
+/// The handheld console. Loop detection is enabled by default and can be turned off for programs
+/// that are meant to revisit instructions.
+#[derive(Clone, Debug)]
+pub struct Vm<'a> {
+    program: &'a [Instruction],
+    operations: Cow<'a, OperationSet>,
+    state: State,
+    steps: u64,
+    visited: FixedBitSet,
+    breakpoints: FixedBitSet,
+    step_limit: Option<u64>,
+    detect_loops: bool,
+    trace: Option<Vec<TraceEntry>>,
+    last_stop: Option<Stop>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Vm::with_operations(program, Cow::Owned(OperationSet::default()))
+    }
+
+    pub fn with_operations(program: &'a [Instruction], operations: Cow<'a, OperationSet>) -> Self {
+        Vm {
+            program,
+            operations,
+            state: State::default(),
+            steps: 0,
+            visited: FixedBitSet::with_capacity(program.len()),
+            breakpoints: FixedBitSet::with_capacity(program.len()),
+            step_limit: None,
+            detect_loops: true,
+            trace: None,
+            last_stop: None,
+        }
+    }
+
+    pub fn program(&self) -> &'a [Instruction] {
+        self.program
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn pc(&self) -> usize {
+        self.state.pc
+    }
+
+    pub fn acc(&self) -> i64 {
+        self.state.acc
+    }
+
+    /// The number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Why the machine stopped last, or `None` if it has not stopped since it last executed an
+    /// instruction.
+    pub fn last_stop(&self) -> Option<Stop> {
+        self.last_stop
+    }
+
+    pub fn has_visited(&self, pc: usize) -> bool {
+        self.visited.contains(pc)
+    }
+
+    pub fn set_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.grow(pc + 1);
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn clear_breakpoint(&mut self, pc: usize) {
+        if pc < self.breakpoints.len() {
+            self.breakpoints.set(pc, false);
+        }
+    }
+
+    /// Stops running after this many executed instructions in total.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.detect_loops = enabled;
+    }
+
+    /// Records every executed instruction from now on, or stops recording and drops the trace.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        match (enabled, &self.trace) {
+            (true, None) => self.trace = Some(Vec::new()),
+            (false, _) => self.trace = None,
+            _ => {}
+        }
+    }
+
+    /// The executed instructions in order, if tracing is enabled.
+    pub fn trace(&self) -> Option<&[TraceEntry]> {
+        self.trace.as_deref()
+    }
+
+    /// The loop the machine is stuck in, which is everything executed since `pc` was last
+    /// executed. Only available with tracing and after the machine stopped with
+    /// [`Stop::InfiniteLoop`].
+    pub fn cycle(&self) -> Option<Cycle> {
+        if self.last_stop != Some(Stop::InfiniteLoop) {
+            return None;
+        }
+        let trace = self.trace.as_ref()?;
+        let start = trace.iter().rposition(|entry| entry.pc == self.state.pc)?;
+        Some(Cycle {
+            entry: self.state.pc,
+            instructions: trace[start..].iter().map(|entry| entry.pc).collect(),
+        })
+    }
+
+    /// Lists the program with every line marked as executed (`*`), not executed (` `) or part of
+    /// the current [`cycle`](Vm::cycle) (`@`), with `>` pointing at `pc`.
+    pub fn listing(&self) -> String {
+        let mut looping = FixedBitSet::with_capacity(self.program.len());
+        if let Some(cycle) = self.cycle() {
+            looping.extend(cycle.instructions);
+        }
+        let width = self.program.len().saturating_sub(1).to_string().len();
+        let mut listing = String::new();
+        for (idx, instruction) in self.program.iter().enumerate() {
+            let marker = if looping.contains(idx) {
+                '@'
+            } else if self.visited.contains(idx) {
+                '*'
+            } else {
+                ' '
+            };
+            let cursor = if idx == self.state.pc { '>' } else { ' ' };
+            let mnemonic = self.operations.mnemonic(instruction.op).unwrap_or("???");
+            listing += &format!(
+                "{}{} {:>width$}: {} {:+}\n",
+                cursor,
+                marker,
+                idx,
+                mnemonic,
+                instruction.arg,
+                width = width
+            );
+        }
+        listing
+    }
+
+    /// Moves the machine back to the start while keeping breakpoints and settings.
+    pub fn reset(&mut self) {
+        self.state = State::default();
+        self.steps = 0;
+        self.visited.clear();
+        self.last_stop = None;
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
+    }
+
+    /// Executes the instruction at `pc`, unless the machine cannot continue from its current state.
+    pub fn step(&mut self) -> Result<(), Stop> {
+        let result = self.execute_next();
+        self.last_stop = result.err();
+        result
+    }
+
+    fn execute_next(&mut self) -> Result<(), Stop> {
+        let pc = self.state.pc;
+        if pc == self.program.len() {
+            return Err(Stop::Terminated);
+        }
+        if pc > self.program.len() {
+            return Err(Stop::OutOfBounds);
+        }
+        if self.visited.contains(pc) && self.detect_loops {
+            return Err(Stop::InfiniteLoop);
+        }
+        let instruction = self.program[pc];
+        self.operations.execute(instruction, &mut self.state)?;
+        self.visited.insert(pc);
+        self.steps += 1;
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEntry {
+                pc,
+                instruction,
+                acc: self.state.acc,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    /// Runs until the machine stops or `condition` holds after an executed instruction. If the
+    /// machine last stopped at the breakpoint it is on, that breakpoint is skipped so that it can
+    /// be resumed.
+    pub fn run_until(&mut self, mut condition: impl FnMut(&Self) -> bool) -> Stop {
+        let stop = loop {
+            let pc = self.state.pc;
+            if self.breakpoints.contains(pc) && self.last_stop != Some(Stop::Breakpoint(pc)) {
+                break Stop::Breakpoint(pc);
+            }
+            if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+                break Stop::StepLimit;
+            }
+            if let Err(stop) = self.step() {
+                break stop;
+            }
+            if condition(self) {
+                break Stop::Condition;
+            }
+        };
+        self.last_stop = Some(stop);
+        stop
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AsmError {
+    Syntax {
+        line: usize,
+        column: usize,
+        expected: &'static str,
+    },
+    UnknownOperation {
+        line: usize,
+        column: usize,
+        mnemonic: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        first_line: usize,
+        label: String,
+    },
+    UndefinedLabel {
+        line: usize,
+        column: usize,
+        label: String,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Syntax {
+                line,
+                column,
+                expected,
+            } => write!(f, "line {}, column {}: expected {}", line, column, expected),
+            AsmError::UnknownOperation {
+                line,
+                column,
+                mnemonic,
+            } => write!(
+                f,
+                "line {}, column {}: unknown operation {:?}",
+                line, column, mnemonic
+            ),
+            AsmError::DuplicateLabel {
+                line,
+                first_line,
+                label,
+            } => write!(
+                f,
+                "line {}: label {:?} is already defined on line {}",
+                line, label, first_line
+            ),
+            AsmError::UndefinedLabel {
+                line,
+                column,
+                label,
+            } => write!(
+                f,
+                "line {}, column {}: label {:?} is not defined",
+                line, column, label
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles one instruction per line. Everything after `#` or `;` is a comment, blank lines are
+/// skipped and a line may start with any number of `label:` definitions. `jmp` and `nop` accept a
+/// label instead of an offset; a label after the last instruction refers to the end of the program.
+pub fn assemble(source: &str, operations: &OperationSet) -> Result<Vec<Instruction>, AsmError> {
+    let mut program = Vec::new();
+    let mut labels = HashMap::<&str, (usize, usize)>::new();
+    let mut references = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_number = line_idx + 1;
+        let column = |rest: &str| line[..line.len() - rest.len()].chars().count() + 1;
+        let syntax = |rest: &str, expected| AsmError::Syntax {
+            line: line_number,
+            column: column(rest),
+            expected,
+        };
+
+        let code = line.find(['#', ';']).map_or(line, |end| &line[..end]);
+        let mut i = code.trim_start();
+        while let Some((label, rest)) = identifier(i).filter(|(_, rest)| rest.starts_with(':')) {
+            if let Some(&(_, first_line)) = labels.get(label) {
+                return Err(AsmError::DuplicateLabel {
+                    line: line_number,
+                    first_line,
+                    label: label.to_owned(),
+                });
+            }
+            labels.insert(label, (program.len(), line_number));
+            i = rest[1..].trim_start();
+        }
+        if i.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = identifier(i).ok_or_else(|| syntax(i, "a label or an operation"))?;
+        let op = operations
+            .lookup(mnemonic)
+            .ok_or_else(|| AsmError::UnknownOperation {
+                line: line_number,
+                column: column(i),
+                mnemonic: mnemonic.to_owned(),
+            })?;
+        let i = rest.trim_start();
+        if i.len() == rest.len() && !rest.is_empty() {
+            return Err(syntax(rest, "whitespace"));
+        }
+
+        let takes_label = matches!(op, Operation::Jmp | Operation::Nop);
+        let (arg, rest) = if let Some((arg, rest)) = number(i) {
+            (arg, rest)
+        } else if let Some((label, rest)) = identifier(i).filter(|_| takes_label) {
+            references.push((program.len(), line_number, column(i), label));
+            (0, rest)
+        } else if takes_label {
+            return Err(syntax(i, "a number or a label"));
+        } else {
+            return Err(syntax(i, "a number"));
+        };
+        if !rest.trim().is_empty() {
+            return Err(syntax(rest.trim_start(), "the end of the line"));
+        }
+        program.push(Instruction::new(op, arg));
+    }
+
+    for (idx, line, column, label) in references {
+        let &(target, _) = labels.get(label).ok_or_else(|| AsmError::UndefinedLabel {
+            line,
+            column,
+            label: label.to_owned(),
+        })?;
+        program[idx].arg = target as i64 - idx as i64;
+    }
+    Ok(program)
+}
+
+fn identifier(i: &str) -> Option<(&str, &str)> {
+    if !i.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return None;
+    }
+    let end = i
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(i.len());
+    Some(i.split_at(end))
+}
+
+fn number(i: &str) -> Option<(i64, &str)> {
+    let digits = i.strip_prefix(['+', '-']).unwrap_or(i);
+    let end = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    if end == 0 {
+        return None;
+    }
+    let end = end + i.len() - digits.len();
+    let arg = i[..end].parse().ok()?;
+    Some((arg, &i[end..]))
+}
+
+/// Writes `program` in canonical form, one instruction per line. With `labels`, jump targets
+/// inside the program or right behind it are named `L1`, `L2`, … in program order.
+pub fn disassemble(
+    program: &[Instruction],
+    operations: &OperationSet,
+    labels: bool,
+) -> anyhow::Result<String> {
+    let mut names = vec![None; program.len() + 1];
+    if labels {
+        for (pc, &instruction) in program.iter().enumerate() {
+            if let (Operation::Jmp, Some(target)) = (instruction.op, successor(pc, instruction)) {
+                if let Some(name) = names.get_mut(target) {
+                    *name = Some(String::new());
                 }
-                RunResult::Terminated(result) => {
-                    return result;
+            }
+        }
+        for (name, number) in names.iter_mut().flatten().zip(1..) {
+            *name = format!("L{}", number);
+        }
+    }
+    let width = names
+        .iter()
+        .flatten()
+        .map(|name| name.len() + 2)
+        .max()
+        .unwrap_or(0);
+
+    let mut source = String::new();
+    for (pc, name) in names.iter().enumerate() {
+        let prefix = name
+            .as_ref()
+            .map_or(String::new(), |name| format!("{}:", name));
+        let instruction = match program.get(pc) {
+            Some(&instruction) => instruction,
+            None => {
+                if !prefix.is_empty() {
+                    source += &prefix;
+                    source.push('\n');
                 }
+                break;
+            }
+        };
+        let mnemonic = operations
+            .mnemonic(instruction.op)
+            .with_context(|| format!("unknown operation at instruction {}", pc))?;
+        let target = match instruction.op {
+            Operation::Jmp => successor(pc, instruction).and_then(|target| names.get(target)),
+            _ => None,
+        };
+        source += &match target {
+            Some(Some(label)) => {
+                format!("{:width$}{} {}\n", prefix, mnemonic, label, width = width)
+            }
+            _ => format!(
+                "{:width$}{} {:+}\n",
+                prefix,
+                mnemonic,
+                instruction.arg,
+                width = width
+            ),
+        };
+    }
+    Ok(source)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Node {
+    Instruction(usize),
+    /// Right behind the last instruction.
+    End,
+    /// Anywhere else outside of the program.
+    OutOfBounds,
+}
+
+/// The control flow of a program. Node `pc` is the instruction at `pc`, followed by [`Node::End`]
+/// and [`Node::OutOfBounds`]. Every instruction has a single successor, except for custom
+/// operations, which have none since their effect on `pc` is unknown.
+#[derive(Clone, Debug)]
+pub struct ControlFlowGraph {
+    graph: DiGraph<Node, (), u32>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(program: &[Instruction]) -> Self {
+        let mut graph = DiGraph::with_capacity(program.len() + 2, program.len());
+        for pc in 0..program.len() {
+            graph.add_node(Node::Instruction(pc));
+        }
+        let end = graph.add_node(Node::End);
+        let out_of_bounds = graph.add_node(Node::OutOfBounds);
+        for (pc, &instruction) in program.iter().enumerate() {
+            if let Operation::Custom(_) = instruction.op {
+                continue;
             }
+            let target = match successor(pc, instruction) {
+                Some(next) if next < program.len() => NodeIndex::new(next),
+                Some(next) if next == program.len() => end,
+                _ => out_of_bounds,
+            };
+            graph.add_edge(NodeIndex::new(pc), target, ());
         }
+        ControlFlowGraph { graph }
+    }
+
+    pub fn graph(&self) -> &DiGraph<Node, (), u32> {
+        &self.graph
+    }
+
+    fn len(&self) -> usize {
+        self.graph.node_count() - 2
+    }
+
+    fn end(&self) -> NodeIndex<u32> {
+        NodeIndex::new(self.len())
+    }
+
+    fn out_of_bounds(&self) -> NodeIndex<u32> {
+        NodeIndex::new(self.len() + 1)
+    }
+
+    fn next(&self, pc: usize) -> Option<NodeIndex<u32>> {
+        self.graph.neighbors(NodeIndex::new(pc)).next()
+    }
+
+    /// Instructions that cannot be reached from the start. Instructions only reachable through a
+    /// custom operation count as unreachable.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let reachable = Dfs::new(&self.graph, NodeIndex::new(0))
+            .iter(&self.graph)
+            .map(NodeIndex::index)
+            .collect::<FixedBitSet>();
+        (0..self.len())
+            .filter(|&pc| !reachable.contains(pc))
+            .collect()
+    }
+
+    /// Jumps to anywhere outside of the program except its end, whether reachable or not.
+    pub fn out_of_bounds_jumps(&self) -> Vec<usize> {
+        let mut jumps = self
+            .graph
+            .neighbors_directed(self.out_of_bounds(), Direction::Incoming)
+            .map(NodeIndex::index)
+            .collect::<Vec<_>>();
+        jumps.sort_unstable();
+        jumps
+    }
+
+    /// Every loop in the program, whether reachable or not, each starting at its smallest `pc`.
+    /// A program that enters one of them never leaves it again.
+    pub fn loops(&self) -> Vec<Cycle> {
+        let mut loops = tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.graph.contains_edge(component[0], component[0])
+            })
+            .map(|component| {
+                let entry = component.iter().map(|node| node.index()).min().unwrap();
+                let mut instructions = vec![entry];
+                let mut pc = self.next(entry).unwrap().index();
+                while pc != entry {
+                    instructions.push(pc);
+                    pc = self.next(pc).unwrap().index();
+                }
+                Cycle {
+                    entry,
+                    instructions,
+                }
+            })
+            .collect::<Vec<_>>();
+        loops.sort_unstable_by_key(|cycle| cycle.entry);
+        loops
     }
-    unreachable!()
 }
 
-enum RunResult {
-    InfiniteLoop(i64),
-    Terminated(i64),
-    Error,
+/// How execution from the start ends, with the accumulator at that point.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Outcome {
+    Terminates {
+        acc: i64,
+    },
+    /// The jump at `pc` leaves the program.
+    OutOfBounds {
+        pc: usize,
+        acc: i64,
+    },
+    /// The `acc` instruction at `pc` would overflow the accumulator.
+    Overflow {
+        pc: usize,
+        acc: i64,
+    },
+    /// Execution is about to enter `cycle` for the second time.
+    InfiniteLoop {
+        cycle: Cycle,
+        acc: i64,
+    },
+    /// The custom operation at `pc` is about to execute, after which nothing is known.
+    Unknown {
+        pc: usize,
+        acc: i64,
+    },
 }
 
-fn run_code(input: &[Instruction]) -> RunResult {
-    let mut has_visited_line_before = vec![false; input.len()];
-    let mut pc = 0usize;
-    let mut acc = 0i64;
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Analysis {
+    pub unreachable: Vec<usize>,
+    pub out_of_bounds_jumps: Vec<usize>,
+    pub loops: Vec<Cycle>,
+    /// The accumulator before each instruction on the path from the start is executed for the
+    /// first time.
+    pub entry_acc: Vec<Option<i64>>,
+    pub outcome: Outcome,
+}
 
-    loop {
-        if pc == input.len() {
-            return RunResult::Terminated(acc);
+impl Analysis {
+    /// Fails if the program is known to loop forever, to jump out of bounds or to overflow the
+    /// accumulator.
+    pub fn check(&self) -> anyhow::Result<()> {
+        match &self.outcome {
+            Outcome::InfiniteLoop { cycle, .. } => anyhow::bail!(
+                "infinite loop of {} instructions starting at {}",
+                cycle.len(),
+                cycle.entry
+            ),
+            Outcome::OutOfBounds { pc, .. } => {
+                anyhow::bail!("jump at {} leaves the program", pc)
+            }
+            Outcome::Overflow { pc, .. } => {
+                anyhow::bail!("instruction {} overflows the accumulator", pc)
+            }
+            Outcome::Terminates { .. } | Outcome::Unknown { .. } => Ok(()),
         }
-        if pc > input.len() {
-            return RunResult::Error;
+    }
+}
+
+/// Analyzes `program` without running it. Since every built-in instruction has a single successor,
+/// the path from the start and the accumulator along it are fully determined up to the first custom
+/// operation.
+pub fn analyze(program: &[Instruction]) -> Analysis {
+    let cfg = ControlFlowGraph::new(program);
+    let mut entry_acc = vec![None; program.len()];
+    let mut trace = Vec::new();
+    let mut acc = 0i64;
+    let mut node = NodeIndex::new(0);
+    let outcome = loop {
+        if node == cfg.end() {
+            break Outcome::Terminates { acc };
         }
-        if replace(&mut has_visited_line_before[pc], true) {
-            return RunResult::InfiniteLoop(acc);
+        let pc = node.index();
+        if entry_acc[pc].is_some() {
+            let start = trace.iter().position(|&visited| visited == pc).unwrap();
+            let cycle = Cycle {
+                entry: pc,
+                instructions: trace.split_off(start),
+            };
+            break Outcome::InfiniteLoop { cycle, acc };
         }
-        let instruction = input[pc];
-        match instruction.op {
-            Operation::Acc => {
-                acc += instruction.arg;
-                pc += 1;
+        entry_acc[pc] = Some(acc);
+        trace.push(pc);
+        if let Operation::Acc = program[pc].op {
+            match acc.checked_add(program[pc].arg) {
+                Some(sum) => acc = sum,
+                None => break Outcome::Overflow { pc, acc },
             }
-            Operation::Jmp => {
-                pc = pc.wrapping_add(instruction.arg as usize);
+        }
+        node = match cfg.next(pc) {
+            Some(next) if next == cfg.out_of_bounds() => break Outcome::OutOfBounds { pc, acc },
+            Some(next) => next,
+            None => break Outcome::Unknown { pc, acc },
+        };
+    };
+
+    Analysis {
+        unreachable: cfg.unreachable(),
+        out_of_bounds_jumps: cfg.out_of_bounds_jumps(),
+        loops: cfg.loops(),
+        entry_acc,
+        outcome,
+    }
+}
+
+#[aoc(day8, part1)]
+pub fn part_1(input: &PartInput) -> anyhow::Result<i64> {
+    let mut vm = Vm::new(input);
+    match vm.run() {
+        Stop::InfiniteLoop => Ok(vm.acc()),
+        stop => anyhow::bail!(
+            "expected an infinite loop, but the program stopped with {:?}",
+            stop
+        ),
+    }
+}
+
+#[aoc(day8, part2)]
+pub fn part_2(input: &PartInput) -> anyhow::Result<i64> {
+    let mut code = input.to_vec();
+    for idx in 0..code.len() {
+        if let Some(patch) = flipped(input[idx]) {
+            code[idx] = patch;
+            let mut vm = Vm::new(&code);
+            if vm.run() == Stop::Terminated {
+                return Ok(vm.acc());
             }
-            Operation::Nop => {
-                pc += 1;
+            code[idx] = input[idx];
+        }
+    }
+    anyhow::bail!("no single swap makes the program terminate")
+}
+
+/// The instruction whose operation has to be swapped between `jmp` and `nop` for the program to
+/// terminate, and the accumulator it terminates with.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Repair {
+    pub index: usize,
+    pub acc: i64,
+}
+
+/// Where control goes after `instruction` at `pc`, or `None` for custom operations and jumps
+/// before the start of the program.
+fn successor(pc: usize, instruction: Instruction) -> Option<usize> {
+    match instruction.op {
+        Operation::Acc | Operation::Nop => Some(pc + 1),
+        Operation::Jmp => (pc as i64)
+            .checked_add(instruction.arg)
+            .and_then(|target| usize::try_from(target).ok()),
+        Operation::Custom(_) => None,
+    }
+}
+
+fn flipped(instruction: Instruction) -> Option<Instruction> {
+    match instruction.op {
+        Operation::Jmp => Some(Instruction::new(Operation::Nop, instruction.arg)),
+        Operation::Nop => Some(Instruction::new(Operation::Jmp, instruction.arg)),
+        _ => None,
+    }
+}
+
+/// Finds the single `jmp`/`nop` swap that makes `program` terminate in O(n). Walking the reverse
+/// control flow graph from the end marks every instruction that terminates as is, so the swap is
+/// the first one on the original path whose new successor is marked and whose patched run does
+/// not overflow. Returns `None` if the program already terminates or no single swap helps.
+pub fn repair(program: &[Instruction]) -> Option<Repair> {
+    let len = program.len();
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (pc, &instruction) in program.iter().enumerate() {
+        if let Some(next) = successor(pc, instruction).filter(|&next| next <= len) {
+            predecessors[next].push(pc);
+        }
+    }
+
+    let mut terminates = FixedBitSet::with_capacity(len + 1);
+    terminates.insert(len);
+    let mut stack = vec![len];
+    while let Some(pc) = stack.pop() {
+        for &prev in &predecessors[pc] {
+            if !terminates.put(prev) {
+                stack.push(prev);
+            }
+        }
+    }
+    if terminates.contains(0) {
+        return None;
+    }
+
+    let mut visited = FixedBitSet::with_capacity(len);
+    let mut pc = 0;
+    while pc < len && !visited.put(pc) {
+        let instruction = program[pc];
+        if let Some(patch) = flipped(instruction) {
+            let patched_target = successor(pc, patch).filter(|&next| next <= len);
+            if patched_target.is_some_and(|next| terminates.contains(next)) {
+                let mut patched = program.to_vec();
+                patched[pc] = patch;
+                let mut vm = Vm::new(&patched);
+                if vm.run() == Stop::Terminated {
+                    return Some(Repair {
+                        index: pc,
+                        acc: vm.acc(),
+                    });
+                }
             }
         }
+        pc = successor(pc, instruction)?;
     }
+    None
+}
+
+#[aoc(day8, part2, repair)]
+pub fn part_2_repair(input: &PartInput) -> anyhow::Result<i64> {
+    repair(input)
+        .map(|repair| repair.acc)
+        .context("no single swap makes the program terminate")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::xorshift::XorShift;
 
     const EXAMPLE: &str = "nop +0
 acc +1
@@ -123,11 +950,423 @@ acc +6";
 
     #[test]
     fn part_1_test() {
-        assert_eq!(part_1(&generator(EXAMPLE).unwrap()), 5);
+        assert_eq!(part_1(&generator(EXAMPLE).unwrap()).unwrap(), 5);
     }
 
     #[test]
     fn part_2_test() {
-        assert_eq!(part_2(&generator(EXAMPLE).unwrap()), 8);
+        assert_eq!(part_2(&generator(EXAMPLE).unwrap()).unwrap(), 8);
+    }
+
+    #[test]
+    fn vm_test() {
+        let program = generator(EXAMPLE).unwrap();
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.step(), Ok(()));
+        assert_eq!(vm.state(), State { pc: 1, acc: 0 });
+        vm.set_breakpoint(4);
+        assert_eq!(vm.run(), Stop::Breakpoint(4));
+        assert_eq!((vm.pc(), vm.acc(), vm.steps()), (4, 5, 6));
+        assert_eq!(vm.run(), Stop::InfiniteLoop);
+        assert_eq!((vm.pc(), vm.acc(), vm.steps()), (1, 5, 7));
+
+        vm.reset();
+        vm.set_loop_detection(false);
+        vm.set_step_limit(Some(100));
+        assert_eq!(vm.run(), Stop::Breakpoint(4));
+        vm.clear_breakpoint(4);
+        assert_eq!(vm.run(), Stop::StepLimit);
+        assert_eq!(vm.steps(), 100);
+
+        vm.reset();
+        assert_eq!(vm.run_until(|vm| vm.acc() >= 3), Stop::Condition);
+        assert_eq!(vm.pc(), 4);
+
+        let mut vm = Vm::new(&program);
+        vm.set_breakpoint(0);
+        assert_eq!(vm.run(), Stop::Breakpoint(0));
+        assert_eq!(vm.steps(), 0);
+        assert_eq!(vm.run(), Stop::InfiniteLoop);
+        vm.reset();
+        assert_eq!(vm.run(), Stop::Breakpoint(0));
+
+        let program = [Instruction::new(Operation::Jmp, -1)];
+        assert_eq!(Vm::new(&program).run(), Stop::OutOfBounds);
+
+        let program = [Instruction::new(Operation::Acc, i64::MAX); 2];
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.run(), Stop::Overflow(1));
+        assert_eq!(
+            vm.state(),
+            State {
+                pc: 1,
+                acc: i64::MAX
+            }
+        );
+    }
+
+    #[test]
+    fn custom_operation_test() {
+        let mut operations = OperationSet::default();
+        let mul = operations
+            .register("mul", |state, arg| {
+                state.acc *= arg;
+                state.pc += 1;
+            })
+            .unwrap();
+        assert!(operations.register("mul", |_, _| {}).is_err());
+        assert!(operations.register("jmp", |_, _| {}).is_err());
+        assert_eq!(operations.lookup("mul"), Some(mul));
+        assert_eq!(operations.mnemonic(mul), Some("mul"));
+
+        let program = [
+            Instruction::new(Operation::Acc, 3),
+            Instruction::new(mul, 7),
+        ];
+        let mut vm = Vm::with_operations(&program, Cow::Borrowed(&operations));
+        assert_eq!(vm.run(), Stop::Terminated);
+        assert_eq!(vm.acc(), 21);
+
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.run(), Stop::UnknownOperation(1));
+    }
+
+    #[test]
+    fn trace_test() {
+        let program = generator(EXAMPLE).unwrap();
+        let mut vm = Vm::new(&program);
+        vm.set_tracing(true);
+        assert_eq!(vm.run(), Stop::InfiniteLoop);
+        let trace = vm.trace().unwrap();
+        assert_eq!(
+            trace.iter().map(|e| (e.pc, e.acc)).collect::<Vec<_>>(),
+            [(0, 0), (1, 1), (2, 1), (6, 2), (7, 2), (3, 5), (4, 5)]
+        );
+        assert_eq!(trace[3].instruction, Instruction::new(Operation::Acc, 1));
+        let cycle = vm.cycle().unwrap();
+        assert_eq!(cycle.entry, 1);
+        assert_eq!(cycle.len(), 6);
+        assert_eq!(cycle.instructions, [1, 2, 6, 7, 3, 4]);
+        assert_eq!(
+            vm.listing(),
+            concat!(
+                " * 0: nop +0\n",
+                ">@ 1: acc +1\n",
+                " @ 2: jmp +4\n",
+                " @ 3: acc +3\n",
+                " @ 4: jmp -3\n",
+                "   5: acc -99\n",
+                " @ 6: acc +1\n",
+                " @ 7: jmp -4\n",
+                "   8: acc +6\n",
+            )
+        );
+
+        vm.reset();
+        vm.set_breakpoint(2);
+        assert_eq!(vm.run(), Stop::Breakpoint(2));
+        assert_eq!(vm.run(), Stop::InfiniteLoop);
+        vm.reset();
+        vm.set_loop_detection(false);
+        assert_eq!(vm.run(), Stop::Breakpoint(2));
+        assert_eq!(vm.run(), Stop::Breakpoint(2));
+        assert_eq!(vm.last_stop(), Some(Stop::Breakpoint(2)));
+        assert_eq!(vm.cycle(), None);
+        assert!(!vm.listing().contains('@'));
+        vm.clear_breakpoint(2);
+        vm.set_step_limit(Some(20));
+        assert_eq!(vm.run(), Stop::StepLimit);
+        assert_eq!(vm.cycle(), None);
+
+        vm.set_tracing(false);
+        assert_eq!(vm.trace(), None);
+        assert_eq!(vm.cycle(), None);
+    }
+
+    #[test]
+    fn repair_test() {
+        let program = generator(EXAMPLE).unwrap();
+        assert_eq!(repair(&program), Some(Repair { index: 7, acc: 8 }));
+        assert_eq!(part_2_repair(&program).unwrap(), 8);
+
+        let terminating = [Instruction::new(Operation::Acc, 1)];
+        assert_eq!(repair(&terminating), None);
+        let terminating = [
+            Instruction::new(Operation::Nop, 1),
+            Instruction::new(Operation::Acc, 1),
+        ];
+        assert_eq!(repair(&terminating), None);
+        assert_eq!(repair(&[]), None);
+        let overflowing = [
+            Instruction::new(Operation::Nop, 3),
+            Instruction::new(Operation::Nop, 4),
+            Instruction::new(Operation::Jmp, -2),
+            Instruction::new(Operation::Acc, i64::MAX),
+            Instruction::new(Operation::Acc, i64::MAX),
+        ];
+        assert_eq!(repair(&overflowing), Some(Repair { index: 1, acc: 0 }));
+        let hopeless = [
+            Instruction::new(Operation::Jmp, 0),
+            Instruction::new(Operation::Jmp, -1),
+        ];
+        assert_eq!(repair(&hopeless), None);
+    }
+
+    /// A program of `acc`, `jmp` and `nop` instructions with arguments in `-max_arg..=max_arg`.
+    fn random_program(
+        rng: &mut XorShift,
+        len: std::ops::Range<usize>,
+        max_arg: i64,
+    ) -> Vec<Instruction> {
+        const OPERATIONS: [Operation; 3] = [Operation::Acc, Operation::Jmp, Operation::Nop];
+        (0..len.start + rng.below(len.len()))
+            .map(|_| {
+                let arg = rng.below(2 * max_arg as usize + 1) as i64 - max_arg;
+                Instruction::new(*rng.pick(&OPERATIONS), arg)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repair_brute_force_test() {
+        fn run(program: &[Instruction]) -> (Stop, i64) {
+            let mut vm = Vm::new(program);
+            (vm.run(), vm.acc())
+        }
+
+        let mut rng = XorShift::new(0x5851_f42d_4c95_7f2d);
+        for _ in 0..500 {
+            let program = random_program(&mut rng, 1..13, 4);
+            let len = program.len();
+            if run(&program).0 == Stop::Terminated {
+                assert_eq!(repair(&program), None, "{:?}", program);
+                continue;
+            }
+            let brute_force = (0..len).find_map(|idx| {
+                let mut patched = program.clone();
+                patched[idx] = flipped(program[idx])?;
+                match run(&patched) {
+                    (Stop::Terminated, acc) => Some(Repair { index: idx, acc }),
+                    _ => None,
+                }
+            });
+            let repaired = repair(&program);
+            assert_eq!(repaired.is_some(), brute_force.is_some(), "{:?}", program);
+            if let Some(repaired) = repaired {
+                let mut patched = program.clone();
+                patched[repaired.index] = flipped(program[repaired.index]).unwrap();
+                assert!(run(&patched) == (Stop::Terminated, repaired.acc));
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_test() {
+        let source = "
+            # count down from 3
+            start:  acc +3
+            loop:   acc -1      ; decrement
+                    jmp loop
+            nop end
+            end:
+        ";
+        assert_eq!(
+            assemble(source, &OperationSet::default()),
+            Ok(vec![
+                Instruction::new(Operation::Acc, 3),
+                Instruction::new(Operation::Acc, -1),
+                Instruction::new(Operation::Jmp, -1),
+                Instruction::new(Operation::Nop, 1),
+            ])
+        );
+
+        let error = |source| assemble(source, &OperationSet::default()).unwrap_err();
+        assert_eq!(
+            error("nop +0\njm +1"),
+            AsmError::UnknownOperation {
+                line: 2,
+                column: 1,
+                mnemonic: "jm".to_owned()
+            }
+        );
+        assert_eq!(
+            error("acc"),
+            AsmError::Syntax {
+                line: 1,
+                column: 4,
+                expected: "a number"
+            }
+        );
+        assert_eq!(
+            error("  acc  start"),
+            AsmError::Syntax {
+                line: 1,
+                column: 8,
+                expected: "a number"
+            }
+        );
+        assert_eq!(
+            error("jmp +1 +2").to_string(),
+            "line 1, column 8: expected the end of the line"
+        );
+        assert_eq!(
+            error("a: nop +0\na: nop +0"),
+            AsmError::DuplicateLabel {
+                line: 2,
+                first_line: 1,
+                label: "a".to_owned()
+            }
+        );
+        assert_eq!(
+            error("nop +0\n\n  jmp nowhere"),
+            AsmError::UndefinedLabel {
+                line: 3,
+                column: 7,
+                label: "nowhere".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn disassemble_test() {
+        let program = generator(EXAMPLE).unwrap();
+        let operations = OperationSet::default();
+        assert_eq!(
+            disassemble(&program, &operations, false).unwrap(),
+            format!("{}\n", EXAMPLE)
+        );
+        assert_eq!(
+            disassemble(&program, &operations, true).unwrap(),
+            concat!(
+                "    nop +0\n",
+                "L1: acc +1\n",
+                "    jmp L3\n",
+                "L2: acc +3\n",
+                "    jmp L1\n",
+                "    acc -99\n",
+                "L3: acc +1\n",
+                "    jmp L2\n",
+                "    acc +6\n",
+            )
+        );
+        let unknown = [Instruction::new(Operation::Custom(0), 0)];
+        assert!(disassemble(&unknown, &operations, false).is_err());
+    }
+
+    #[test]
+    fn disassemble_round_trip_test() {
+        let mut rng = XorShift::new(0x1405_7b7e_f767_814f);
+        let operations = OperationSet::default();
+        for _ in 0..200 {
+            let program = random_program(&mut rng, 0..16, 20);
+            for &labels in &[false, true] {
+                let source = disassemble(&program, &operations, labels).unwrap();
+                assert_eq!(
+                    assemble(&source, &operations),
+                    Ok(program.clone()),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn analyze_test() {
+        let mut program = generator(EXAMPLE).unwrap();
+        let analysis = analyze(&program);
+        let cycle = Cycle {
+            entry: 1,
+            instructions: vec![1, 2, 6, 7, 3, 4],
+        };
+        assert_eq!(analysis.unreachable, [5, 8]);
+        assert_eq!(analysis.out_of_bounds_jumps, []);
+        assert_eq!(analysis.loops, std::slice::from_ref(&cycle));
+        assert_eq!(
+            analysis.entry_acc,
+            [
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(2),
+                Some(5),
+                None,
+                Some(1),
+                Some(2),
+                None
+            ]
+        );
+        assert_eq!(analysis.outcome, Outcome::InfiniteLoop { cycle, acc: 5 });
+        assert!(analysis.check().is_err());
+
+        program[7].op = Operation::Nop;
+        let analysis = analyze(&program);
+        assert_eq!(analysis.unreachable, [3, 4, 5]);
+        assert_eq!(analysis.loops, []);
+        assert_eq!(analysis.outcome, Outcome::Terminates { acc: 8 });
+        assert!(analysis.check().is_ok());
+
+        let program = assemble(
+            "acc +2\njmp -2\nloop: acc +1\njmp loop",
+            &OperationSet::default(),
+        )
+        .unwrap();
+        let analysis = analyze(&program);
+        assert_eq!(analysis.unreachable, [2, 3]);
+        assert_eq!(analysis.out_of_bounds_jumps, [1]);
+        assert_eq!(analysis.loops.len(), 1);
+        assert_eq!(analysis.loops[0].instructions, [2, 3]);
+        assert_eq!(analysis.outcome, Outcome::OutOfBounds { pc: 1, acc: 2 });
+
+        let program = [
+            Instruction::new(Operation::Acc, 4),
+            Instruction::new(Operation::Custom(0), 0),
+            Instruction::new(Operation::Nop, 0),
+        ];
+        let analysis = analyze(&program);
+        assert_eq!(analysis.unreachable, [2]);
+        assert_eq!(analysis.outcome, Outcome::Unknown { pc: 1, acc: 4 });
+        assert_eq!(analyze(&[]).outcome, Outcome::Terminates { acc: 0 });
+
+        let program = [Instruction::new(Operation::Acc, i64::MAX); 2];
+        let analysis = analyze(&program);
+        assert_eq!(
+            analysis.outcome,
+            Outcome::Overflow {
+                pc: 1,
+                acc: i64::MAX
+            }
+        );
+        assert!(analysis.check().is_err());
+    }
+
+    #[test]
+    fn analyze_vm_test() {
+        let mut rng = XorShift::new(0xd1b5_4a32_d192_ed03);
+        for _ in 0..500 {
+            let program = random_program(&mut rng, 0..12, 5);
+            let mut vm = Vm::new(&program);
+            vm.set_tracing(true);
+            let stop = vm.run();
+            let analysis = analyze(&program);
+            match (stop, &analysis.outcome) {
+                (Stop::Terminated, Outcome::Terminates { acc }) => assert_eq!(vm.acc(), *acc),
+                (Stop::OutOfBounds, Outcome::OutOfBounds { acc, .. }) => assert_eq!(vm.acc(), *acc),
+                (Stop::Overflow(pc), Outcome::Overflow { pc: at, acc }) => {
+                    assert_eq!((pc, vm.acc()), (*at, *acc))
+                }
+                (Stop::InfiniteLoop, Outcome::InfiniteLoop { cycle, acc }) => {
+                    assert_eq!(vm.acc(), *acc);
+                    assert_eq!(vm.cycle().as_ref(), Some(cycle));
+                }
+                (stop, outcome) => panic!("{:?} vs {:?} for {:?}", stop, outcome, program),
+            }
+            for pc in 0..program.len() {
+                assert_eq!(vm.has_visited(pc), analysis.entry_acc[pc].is_some());
+                if vm.has_visited(pc) {
+                    assert!(!analysis.unreachable.contains(&pc));
+                }
+            }
+        }
     }
 }
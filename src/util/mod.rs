@@ -1 +1,3 @@
 pub(crate) mod parsers;
+#[cfg(test)]
+pub(crate) mod xorshift;